@@ -0,0 +1,372 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use warp::http;
+use crate::apiError::ApiError;
+use crate::{CompetitionDivisionPair, StateStore};
+
+#[derive(Deserialize, Debug)]
+pub struct RotateKeyRequest {
+    /// the app whose credentials change; the default app if left out
+    #[serde(default)]
+    app_id: Option<String>,
+    key_id: String,
+    /// the new `.p8` contents
+    private_key: String,
+}
+
+/// Whether `authorization` carries the configured admin token. Without one configured the
+/// admin routes are closed.
+pub fn authorized(admin_token: Option<&str>, authorization: Option<&str>) -> bool {
+    let (Some(expected), Some(given)) = (admin_token, authorization.and_then(|value| value.strip_prefix("Bearer "))) else {
+        return false;
+    };
+
+    // compare every byte so the response time doesn't give away how much of the token matched
+    expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn require_admin(authorization: Option<String>, state_store: &StateStore) -> Result<(), warp::Rejection> {
+    if authorized(state_store.config.admin_token.as_deref(), authorization.as_deref()) {
+        Ok(())
+    } else {
+        Err(ApiError::unauthorized("invalid admin token").into())
+    }
+}
+
+fn unknown_device_token() -> ApiError {
+    ApiError::not_found("unknown_device_token", "no subscriptions for that device token")
+}
+
+/// `POST /v1/admin/apns/keys`: sign with a new APNs key from now on. Every app sharing the
+/// old credentials switches with it; pushes already in flight finish on the old token.
+pub async fn rotate_apns_key(
+    authorization: Option<String>,
+    request: RotateKeyRequest,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_admin(authorization, &state_store)?;
+
+    let Some(client) = state_store.push_providers.apns_client(request.app_id.as_deref()) else {
+        return Err(ApiError::not_found("unknown_app", "unknown app_id").into());
+    };
+
+    // secrets pasted into JSON by hand often keep their newlines escaped
+    let private_key = request.private_key.replace("\\n", "\n");
+    if let Err(e) = client.rotate_key(&request.key_id, private_key.as_bytes()) {
+        return Err(ApiError::bad_request("invalid_key", e.to_string()).into());
+    }
+
+    tracing::info!(app_id = client.bundle_id(), key_id = request.key_id, "rotated APNs key");
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({ "app_id": client.bundle_id(), "key_id": client.key_id() })),
+        http::StatusCode::OK,
+    ))
+}
+
+/// `GET /v1/admin/status`: subscriptions and poll results per division, and each app's APNs token.
+pub async fn status(
+    authorization: Option<String>,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_admin(authorization, &state_store)?;
+
+    let subscriptions: HashMap<CompetitionDivisionPair, usize> = state_store.subscriptions.read().await.iter()
+        .map(|(competition_division, devices)| (competition_division.clone(), devices.len()))
+        .collect();
+    let health = state_store.poll_health.divisions();
+
+    let mut divisions: Vec<&CompetitionDivisionPair> = subscriptions.keys().chain(health.keys()).collect();
+    divisions.sort_by_key(|competition_division| (competition_division.competition_id, competition_division.division_id));
+    divisions.dedup();
+
+    let divisions: Vec<_> = divisions.into_iter()
+        .map(|competition_division| {
+            let division_health = health.get(competition_division).cloned().unwrap_or_default();
+            json!({
+                "competition_id": competition_division.competition_id,
+                "division_id": competition_division.division_id,
+                "subscriptions": subscriptions.get(competition_division).copied().unwrap_or_default(),
+                "last_polled": division_health.last_polled,
+                "last_error": division_health.last_error,
+                "owned": state_store.cluster.owns(competition_division),
+            })
+        })
+        .collect();
+
+    let apns: Vec<_> = state_store.push_providers.apns_clients()
+        .map(|client| json!({
+            "app_id": client.bundle_id(),
+            "key_id": client.key_id(),
+            "token_age_secs": client.token_age().map(|age| age.as_secs()),
+        }))
+        .collect();
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({
+            "instance_id": state_store.cluster.instance_id(),
+            "last_poll": state_store.poll_health.last_poll(),
+            "divisions": divisions,
+            "apns": apns,
+        })),
+        http::StatusCode::OK,
+    ))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SubscriptionsQuery {
+    competition_id: Option<i32>,
+    division_id: Option<i32>,
+}
+
+/// `GET /v1/admin/subscriptions`: every subscription, or those for `?competition_id=` (and `&division_id=`).
+pub async fn subscriptions(
+    query: SubscriptionsQuery,
+    authorization: Option<String>,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_admin(authorization, &state_store)?;
+
+    let subscriptions = state_store.subscription_records(query.competition_id, query.division_id).await;
+    Ok(warp::reply::json(&json!({ "subscriptions": subscriptions })))
+}
+
+/// `GET /v1/admin/devices/{token}/content-state`: what each of the token's subscriptions would
+/// be pushed right now, from the cached match lists.
+pub async fn content_state(
+    device_token: String,
+    authorization: Option<String>,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_admin(authorization, &state_store)?;
+
+    let states = state_store.content_states(&device_token).await;
+    if states.is_empty() {
+        return Err(unknown_device_token().into());
+    }
+
+    let subscriptions: Vec<_> = states.into_iter()
+        .map(|(subscription, content_state)| json!({ "subscription": subscription, "content_state": content_state }))
+        .collect();
+    Ok(warp::reply::json(&json!({ "subscriptions": subscriptions })))
+}
+
+/// `POST /v1/admin/devices/{token}/test-push`: push the token its current content state now.
+pub async fn test_push(
+    device_token: String,
+    authorization: Option<String>,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_admin(authorization, &state_store)?;
+
+    let results = state_store.test_push_notifs(Some(&device_token)).await;
+    if results.is_empty() {
+        return Err(unknown_device_token().into());
+    }
+
+    let pushes: Vec<_> = results.into_iter()
+        .map(|(subscription, result)| json!({ "subscription": subscription, "error": result.err() }))
+        .collect();
+    Ok(warp::reply::json(&json!({ "pushes": pushes })))
+}
+
+/// `DELETE /v1/admin/devices/{token}`: drop every subscription for the token.
+pub async fn remove_device(
+    device_token: String,
+    authorization: Option<String>,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_admin(authorization, &state_store)?;
+
+    let removed = state_store.remove_device_token(&device_token).await?;
+    if removed.is_empty() {
+        return Err(unknown_device_token().into());
+    }
+
+    Ok(warp::reply::json(&json!({ "removed": removed })))
+}
+
+/// `POST /v1/admin/divisions/{competition_id}/{division_id}/repoll`: fetch the division from
+/// RobotEvents now, without waiting for the next poll, and push whatever changed.
+pub async fn repoll(
+    competition_id: i32,
+    division_id: i32,
+    authorization: Option<String>,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_admin(authorization, &state_store)?;
+
+    let competition_division = CompetitionDivisionPair::new(competition_id, division_id);
+    let matches = state_store.poll_division(&competition_division).await
+        .map_err(|e| ApiError::new(http::StatusCode::BAD_GATEWAY, "robotevents_error", e.to_string()))?;
+
+    Ok(warp::reply::json(&json!({
+        "competition_id": competition_id,
+        "division_id": division_id,
+        "matches": matches,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::matchSource::{FixtureClock, FixtureSource, Recording, Snapshot};
+    use crate::tests::{test_match, LIVE_ACTIVITY_TOKEN};
+    use crate::mockApns::{MockApns, MockApnsConfig, MOCK_SIGNING_KEY};
+    use crate::pushProvider::PushProviders;
+    use std::sync::Arc;
+
+    fn admin_config() -> Config {
+        Config {
+            admin_token: Some("let-me-in".to_string()),
+            ..Config::default()
+        }
+    }
+
+    fn admin_store(mock: &MockApns) -> StateStore {
+        StateStore::with_clients(
+            admin_config(),
+            PushProviders::new(mock.client(), None),
+            Arc::new(FixtureSource::new(Vec::new(), FixtureClock::manual())),
+        )
+    }
+
+    #[tokio::test]
+    async fn rotates_the_signing_key_for_an_app() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = admin_store(&mock);
+        let routes = crate::routes(store.clone());
+
+        let rotate = |authorization: &str, body: serde_json::Value| warp::test::request()
+            .method("POST")
+            .path("/v1/admin/apns/keys")
+            .header("authorization", authorization)
+            .json(&body)
+            .reply(&routes);
+
+        let new_key = json!({ "key_id": "ROTATED001", "private_key": MOCK_SIGNING_KEY });
+        assert_eq!(rotate("Bearer let-me-out", new_key.clone()).await.status(), http::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            rotate("Bearer let-me-in", json!({ "key_id": "ROTATED001", "private_key": "not a key" })).await.status(),
+            http::StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            rotate("Bearer let-me-in", json!({ "app_id": "net.dickhans.Unknown", "key_id": "ROTATED001", "private_key": MOCK_SIGNING_KEY })).await.status(),
+            http::StatusCode::NOT_FOUND
+        );
+
+        let client = store.push_providers.apns_client(None).unwrap();
+        let old_token = client.get_token().unwrap();
+        assert_eq!(rotate("Bearer let-me-in", new_key).await.status(), http::StatusCode::OK);
+        assert_eq!(client.key_id(), "ROTATED001");
+        assert_ne!(client.get_token().unwrap(), old_token);
+    }
+
+    #[tokio::test]
+    async fn status_reports_each_division() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let recording = Recording {
+            competition_id: 1,
+            division_id: 1,
+            snapshots: vec![Snapshot { offset_secs: 0, matches: vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (0, 0))] }],
+        };
+        let store = StateStore::with_clients(
+            admin_config(),
+            PushProviders::new(mock.client(), None),
+            Arc::new(FixtureSource::new(vec![recording], FixtureClock::manual())),
+        );
+        let routes = crate::routes(store.clone());
+        let status = |authorization: &str| warp::test::request()
+            .path("/v1/admin/status")
+            .header("authorization", authorization)
+            .reply(&routes);
+
+        store.add_subscription_from_device(crate::DeviceSubscription {
+            competition_id: 1,
+            division_id: 1,
+            device_token: "0a1b2c3d".to_string(),
+            watch_team: "5839A".to_string(),
+            token_type: Default::default(),
+            platform: Default::default(),
+            app_id: None,
+            ttl_secs: None,
+            install_id: None,
+        }).await.unwrap();
+        store.poll_health.fetch_failed(&CompetitionDivisionPair::new(2, 1), "timed out".to_string());
+        store.update_all_subscriptions().await;
+
+        assert_eq!(status("Bearer nope").await.status(), http::StatusCode::UNAUTHORIZED);
+
+        let response = status("Bearer let-me-in").await;
+        assert_eq!(response.status(), http::StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert!(body["last_poll"].is_string());
+        assert_eq!(body["divisions"][0]["subscriptions"], 1);
+        assert!(body["divisions"][0]["last_polled"].is_string());
+        assert_eq!(body["divisions"][1]["subscriptions"], 0);
+        assert_eq!(body["divisions"][1]["last_error"]["message"], "timed out");
+        assert_eq!(body["apns"][0]["app_id"], crate::config::DEFAULT_BUNDLE_ID);
+    }
+
+    #[tokio::test]
+    async fn inspects_repolls_and_removes_a_token() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let recording = Recording {
+            competition_id: 1,
+            division_id: 1,
+            snapshots: vec![Snapshot { offset_secs: 0, matches: vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (0, 0))] }],
+        };
+        let store = StateStore::with_clients(
+            admin_config(),
+            PushProviders::new(mock.client(), None),
+            Arc::new(FixtureSource::new(vec![recording], FixtureClock::manual())),
+        );
+        let routes = crate::routes(store.clone());
+        let admin = |method: &str, path: String| warp::test::request()
+            .method(method)
+            .path(&path)
+            .header("authorization", "Bearer let-me-in")
+            .reply(&routes);
+        let body = |response: &http::Response<warp::hyper::body::Bytes>| -> serde_json::Value {
+            serde_json::from_slice(response.body()).unwrap()
+        };
+
+        store.add_subscription_from_device(crate::DeviceSubscription {
+            competition_id: 1,
+            division_id: 1,
+            device_token: LIVE_ACTIVITY_TOKEN.to_string(),
+            watch_team: "5839A".to_string(),
+            token_type: Default::default(),
+            platform: Default::default(),
+            app_id: None,
+            ttl_secs: None,
+            install_id: None,
+        }).await.unwrap();
+
+        let listed = admin("GET", "/v1/admin/subscriptions?competition_id=1".to_string()).await;
+        assert_eq!(body(&listed)["subscriptions"][0]["device_token"], LIVE_ACTIVITY_TOKEN);
+        assert_eq!(body(&admin("GET", "/v1/admin/subscriptions?competition_id=2".to_string()).await)["subscriptions"], json!([]));
+
+        let repolled = admin("POST", "/v1/admin/divisions/1/1/repoll".to_string()).await;
+        assert_eq!(body(&repolled)["matches"], 1);
+        assert_eq!(mock.deliveries().len(), 1);
+
+        let state = admin("GET", format!("/v1/admin/devices/{}/content-state", LIVE_ACTIVITY_TOKEN)).await;
+        assert_eq!(body(&state)["subscriptions"][0]["content_state"]["teamNextMatch"]["name"], "Q 1");
+
+        let pushed = admin("POST", format!("/v1/admin/devices/{}/test-push", LIVE_ACTIVITY_TOKEN)).await;
+        assert_eq!(body(&pushed)["pushes"][0]["error"], serde_json::Value::Null);
+        assert_eq!(mock.deliveries().len(), 2);
+
+        let removed = admin("DELETE", format!("/v1/admin/devices/{}", LIVE_ACTIVITY_TOKEN)).await;
+        assert_eq!(body(&removed)["removed"].as_array().unwrap().len(), 1);
+        assert!(store.subscriptions.read().await.is_empty());
+        assert_eq!(
+            admin("POST", format!("/v1/admin/devices/{}/test-push", LIVE_ACTIVITY_TOKEN)).await.status(),
+            http::StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use hyper::{Body, Client, Method, Request};
+use hyper_rustls::HttpsConnectorBuilder;
+use std::error::Error;
+
+/// Talks to a running backend's `/v1/admin` routes.
+#[derive(clap::Args, Debug)]
+pub struct AdminArgs {
+    /// the backend to act on
+    #[arg(long, env = "ECHOSCOPE_URL", default_value = "http://127.0.0.1:8080")]
+    url: String,
+    /// the backend's `admin_token`
+    #[arg(long, env = "ADMIN_TOKEN", hide_env_values = true)]
+    token: String,
+    #[command(subcommand)]
+    command: AdminCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum AdminCommand {
+    /// The poll loop, each division's last poll and the APNs keys in use
+    Status,
+    /// List subscriptions, optionally for one event or division
+    Subscriptions {
+        #[arg(long)]
+        competition_id: Option<i32>,
+        #[arg(long, requires = "competition_id")]
+        division_id: Option<i32>,
+    },
+    /// Show the content state each of a token's subscriptions would be pushed right now
+    ContentState { device_token: String },
+    /// Fetch a division from RobotEvents now and push whatever changed
+    Repoll { competition_id: i32, division_id: i32 },
+    /// Push a token its current content state
+    TestPush { device_token: String },
+    /// Drop every subscription for a token
+    Remove { device_token: String },
+}
+
+impl AdminCommand {
+    fn request(&self) -> (Method, String) {
+        match self {
+            AdminCommand::Status => (Method::GET, "/v1/admin/status".to_string()),
+            AdminCommand::Subscriptions { competition_id, division_id } => {
+                let query: Vec<String> = [("competition_id", competition_id), ("division_id", division_id)].iter()
+                    .filter_map(|(name, value)| value.map(|value| format!("{}={}", name, value)))
+                    .collect();
+                let query = if query.is_empty() { String::new() } else { format!("?{}", query.join("&")) };
+                (Method::GET, format!("/v1/admin/subscriptions{}", query))
+            }
+            AdminCommand::ContentState { device_token } => (Method::GET, format!("/v1/admin/devices/{}/content-state", device_token)),
+            AdminCommand::Repoll { competition_id, division_id } => {
+                (Method::POST, format!("/v1/admin/divisions/{}/{}/repoll", competition_id, division_id))
+            }
+            AdminCommand::TestPush { device_token } => (Method::POST, format!("/v1/admin/devices/{}/test-push", device_token)),
+            AdminCommand::Remove { device_token } => (Method::DELETE, format!("/v1/admin/devices/{}", device_token)),
+        }
+    }
+}
+
+/// Make the admin request and print the backend's answer as indented JSON.
+pub async fn run(args: AdminArgs) -> Result<(), Box<dyn Error>> {
+    let (method, path) = args.command.request();
+
+    let https = HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client = Client::builder().build::<_, Body>(https);
+
+    let request = Request::builder()
+        .method(method)
+        .uri(format!("{}{}", args.url.trim_end_matches('/'), path))
+        .header("authorization", format!("Bearer {}", args.token))
+        .body(Body::empty())?;
+
+    let response = client.request(request).await
+        .map_err(|e| format!("Unable to reach {}: {}", args.url, e))?;
+    let status = response.status();
+    let body: serde_json::Value = serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await?)
+        .map_err(|e| format!("{} answered {} with something other than JSON: {}", args.url, status, e))?;
+
+    if !status.is_success() {
+        return Err(format!("{}: {}", status, body["error"].as_str().unwrap_or_default()).into());
+    }
+
+    println!("{}", serde_json::to_string_pretty(&body)?);
+    Ok(())
+}
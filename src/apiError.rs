@@ -0,0 +1,104 @@
+use serde_json::json;
+use std::fmt;
+use std::time::Duration;
+use warp::http;
+use warp::reply::Response;
+use warp::{Rejection, Reply};
+
+/// Why a request was turned away, answered as `{"error": message, "code": code}`.
+/// `code` is stable for clients to match on; `message` is for people.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: http::StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    /// sent as `Retry-After` (rounded up to whole seconds)
+    pub retry_after: Option<Duration>,
+}
+
+impl ApiError {
+    pub fn new(status: http::StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(http::StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(http::StatusCode::UNAUTHORIZED, "unauthorized", message)
+    }
+
+    pub fn not_found(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(http::StatusCode::NOT_FOUND, code, message)
+    }
+
+    pub fn with_retry_after(self, retry_after: Duration) -> Self {
+        ApiError { retry_after: Some(retry_after), ..self }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl warp::reject::Reject for ApiError {}
+
+impl Reply for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = warp::reply::with_status(
+            warp::reply::json(&json!({ "error": self.message, "code": self.code })),
+            self.status,
+        ).into_response();
+
+        if let Some(retry_after) = self.retry_after {
+            let secs = (retry_after.as_secs_f64().ceil() as u64).max(1);
+            response.headers_mut().insert("retry-after", http::HeaderValue::from(secs));
+        }
+
+        response
+    }
+}
+
+/// Answer our own rejections and warp's usual ones (bad JSON, wrong method, no such route)
+/// with a JSON error. Anything else keeps warp's default response.
+pub async fn recover(rejection: Rejection) -> Result<ApiError, Rejection> {
+    use warp::reject;
+
+    if let Some(error) = rejection.find::<ApiError>() {
+        return Ok(error.clone());
+    }
+
+    let error = if rejection.is_not_found() {
+        ApiError::not_found("not_found", "no such route")
+    } else if let Some(e) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
+        ApiError::bad_request("invalid_body", e.to_string())
+    } else if let Some(e) = rejection.find::<reject::InvalidQuery>() {
+        ApiError::bad_request("invalid_query", e.to_string())
+    } else if let Some(e) = rejection.find::<reject::MissingHeader>() {
+        ApiError::bad_request("missing_header", e.to_string())
+    } else if let Some(e) = rejection.find::<reject::InvalidHeader>() {
+        ApiError::bad_request("invalid_header", e.to_string())
+    } else if let Some(e) = rejection.find::<reject::LengthRequired>() {
+        ApiError::new(http::StatusCode::LENGTH_REQUIRED, "length_required", e.to_string())
+    } else if let Some(e) = rejection.find::<reject::PayloadTooLarge>() {
+        ApiError::new(http::StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", e.to_string())
+    } else if let Some(e) = rejection.find::<reject::UnsupportedMediaType>() {
+        ApiError::new(http::StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type", e.to_string())
+    } else if let Some(e) = rejection.find::<reject::MethodNotAllowed>() {
+        ApiError::new(http::StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", e.to_string())
+    } else {
+        return Err(rejection);
+    };
+
+    Ok(error)
+}
@@ -0,0 +1,319 @@
+use hyper::{Body, Client, Method, Request};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::metrics::METRICS;
+
+pub const APNS_SANDBOX_URL: &str = "https://api.sandbox.push.apple.com";
+pub const APNS_PRODUCTION_URL: &str = "https://api.push.apple.com";
+
+/// The `apns-push-type` values this backend knows how to send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApnsPushType {
+    Alert,
+    Background,
+    LiveActivity,
+}
+
+impl ApnsPushType {
+    fn header_value(&self) -> &'static str {
+        match self {
+            ApnsPushType::Alert => "alert",
+            ApnsPushType::Background => "background",
+            ApnsPushType::LiveActivity => "liveactivity",
+        }
+    }
+
+    /// Live Activities use their own topic, everything else goes to the plain bundle ID.
+    fn topic(&self, bundle_id: &str) -> String {
+        match self {
+            ApnsPushType::LiveActivity => format!("{}.push-type.liveactivity", bundle_id),
+            _ => bundle_id.to_string(),
+        }
+    }
+
+    /// Apple rejects background pushes sent with priority 10.
+    fn priority(&self) -> &'static str {
+        match self {
+            ApnsPushType::Background => "5",
+            _ => "10",
+        }
+    }
+}
+
+/// The key provider tokens are signed with, parsed once, plus the token it last signed.
+struct Signer {
+    team_id: String,
+    key_id: String,
+    key: EncodingKey,
+    current_token: Option<(String, SystemTime)>,
+}
+
+impl fmt::Debug for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Signer")
+            .field("team_id", &self.team_id)
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApnsClient {
+    client: Client<HttpsConnector<hyper::client::HttpConnector>>,
+    token_expiration: Duration,
+    /// shared between clones, so every channel and app on these credentials signs with one
+    /// token and picks up a rotated key together
+    signer: Arc<Mutex<Signer>>,
+    bundle_id: String,
+    base_url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    iss: String,
+    iat: u64,
+}
+
+/// Parse a `.p8` key and make sure it can actually sign an APNs provider token.
+pub fn parse_signing_key(team_id: &str, key_id: &str, private_key: &[u8]) -> Result<EncodingKey, Box<dyn Error + Send + Sync>> {
+    let key = EncodingKey::from_ec_pem(private_key)
+        .map_err(|e| format!("not a PKCS#8 EC private key: {}", e))?;
+    sign_token(team_id, key_id, &key)?;
+
+    Ok(key)
+}
+
+fn sign_token(team_id: &str, key_id: &str, key: &EncodingKey) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    // Use a simplified claims structure that matches Apple's requirements
+    let claims = Claims {
+        iss: team_id.to_string(),
+        iat: now,
+    };
+
+    // Configure the header with required fields for APNs
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some(key_id.to_string());
+    // Remove typ field by using a custom header
+    header.typ = None;
+
+    Ok(encode(&header, &claims, key)?)
+}
+
+fn http2_client(allow_http: bool) -> Client<HttpsConnector<hyper::client::HttpConnector>> {
+    let https = HttpsConnectorBuilder::new().with_native_roots();
+    let https = if allow_http { https.https_or_http() } else { https.https_only() };
+
+    Client::builder()
+        .http2_only(true)
+        .build::<_, Body>(https.enable_http2().build())
+}
+
+impl ApnsClient {
+    /// Build a client from the contents of a `.p8` key. Fails if the key can't sign a
+    /// provider token.
+    pub fn from_key(
+        team_id: &str,
+        key_id: &str,
+        private_key: &[u8],
+        bundle_id: &str,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let key = parse_signing_key(team_id, key_id, private_key)?;
+
+        Ok(ApnsClient {
+            client: http2_client(false),
+            token_expiration: Duration::from_secs(55 * 60), // 55 minutes
+            signer: Arc::new(Mutex::new(Signer {
+                team_id: team_id.to_string(),
+                key_id: key_id.to_string(),
+                key,
+                current_token: None,
+            })),
+            bundle_id: bundle_id.to_string(),
+            base_url: APNS_SANDBOX_URL.to_string(),
+        })
+    }
+
+    /// Accept a plain `http://` endpoint as well. Only the mock server needs this; Apple's are
+    /// always https, and the client refuses anything else unless told otherwise.
+    pub fn allowing_http(mut self) -> Self {
+        self.client = http2_client(true);
+        self
+    }
+
+    /// Override the APNs endpoint, e.g. production or a local mock server.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// The same credentials (and cached provider token) pushing to another app on the same team.
+    pub fn with_bundle_id(mut self, bundle_id: &str) -> Self {
+        self.bundle_id = bundle_id.to_string();
+        self
+    }
+
+    pub fn bundle_id(&self) -> &str {
+        &self.bundle_id
+    }
+
+    pub fn key_id(&self) -> String {
+        self.signer.lock().unwrap().key_id.clone()
+    }
+
+    /// How long ago the cached provider token was signed, if one has been.
+    pub fn token_age(&self) -> Option<Duration> {
+        let signer = self.signer.lock().unwrap();
+        let (_, created_at) = signer.current_token.as_ref()?;
+
+        created_at.elapsed().ok()
+    }
+
+    /// How long a provider token is reused before signing a new one.
+    pub fn with_token_lifetime(mut self, token_lifetime: Duration) -> Self {
+        self.token_expiration = token_lifetime;
+        self
+    }
+
+    /// Switch to a new signing key. The key is checked before anything changes; pushes
+    /// already in flight keep the token they were sent with, which Apple honours until
+    /// the old key is revoked.
+    pub fn rotate_key(&self, key_id: &str, private_key: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut signer = self.signer.lock().unwrap();
+        let key = parse_signing_key(&signer.team_id, key_id, private_key)?;
+
+        signer.key_id = key_id.to_string();
+        signer.key = key;
+        signer.current_token = None;
+
+        Ok(())
+    }
+
+    pub fn get_token(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let mut signer = self.signer.lock().unwrap();
+        if let Some((token, created_at)) = &signer.current_token {
+            let now = SystemTime::now();
+            if now.duration_since(*created_at)? < self.token_expiration {
+                return Ok(token.clone());
+            }
+        }
+
+        let token = sign_token(&signer.team_id, &signer.key_id, &signer.key)?;
+        signer.current_token = Some((token.clone(), SystemTime::now()));
+        METRICS.token_refreshes.with_label_values(&["apns"]).inc();
+        Ok(token)
+    }
+
+    pub async fn send_notification(
+        &self,
+        device_token: &str,
+        push_type: ApnsPushType,
+        payload: &Value,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let token = self.get_token()?;
+
+        // Create the URI
+        let uri = format!("{}/3/device/{}", self.base_url, device_token);
+
+        // Build the request
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header("authorization", format!("bearer {}", token))
+            .header("apns-topic", push_type.topic(&self.bundle_id))
+            .header("apns-push-type", push_type.header_value())
+            .header("apns-priority", push_type.priority())
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_string(payload)?))?;
+
+        let pushes = |result: &str, reason: &str| METRICS.apns_pushes
+            .with_label_values(&[push_type.header_value(), result, reason])
+            .inc();
+
+        let res = match self.client.request(req).await {
+            Ok(res) => res,
+            Err(e) => {
+                pushes("failed", "Unreachable");
+                return Err(e.into());
+            }
+        };
+
+        tracing::debug!(status = %res.status(), "APNs response");
+
+        if !res.status().is_success() {
+            let status = res.status();
+            let body_bytes = hyper::body::to_bytes(res.into_body()).await?;
+            let body_str = String::from_utf8_lossy(&body_bytes);
+
+            // Apple explains every rejection as `{"reason": "BadDeviceToken"}` and the like
+            let reason = serde_json::from_slice::<Value>(&body_bytes).ok()
+                .and_then(|body| body.get("reason")?.as_str().map(str::to_string))
+                .unwrap_or_else(|| status.as_u16().to_string());
+            pushes("failed", &reason);
+
+            return Err(format!("APNs error: {}", body_str).into());
+        }
+
+        pushes("sent", "");
+        Ok(())
+    }
+
+    /// Send a user-visible banner. `data` is merged into the top level of the payload
+    /// alongside `aps` so the app can act on it when the notification is opened.
+    pub async fn send_alert(
+        &self,
+        device_token: &str,
+        title: &str,
+        body: &str,
+        data: &Value,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut payload = json!({
+            "aps": {
+                "alert": {
+                    "title": title,
+                    "body": body
+                },
+                "sound": "default"
+            }
+        });
+        merge_custom_data(&mut payload, data);
+
+        self.send_notification(device_token, ApnsPushType::Alert, &payload)
+            .await
+    }
+
+    /// Send a silent `content-available` push carrying `data` for the app to process
+    /// in the background.
+    pub async fn send_background(
+        &self,
+        device_token: &str,
+        data: &Value,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut payload = json!({
+            "aps": {
+                "content-available": 1
+            }
+        });
+        merge_custom_data(&mut payload, data);
+
+        self.send_notification(device_token, ApnsPushType::Background, &payload)
+            .await
+    }
+}
+
+fn merge_custom_data(payload: &mut Value, data: &Value) {
+    if let (Some(payload), Some(data)) = (payload.as_object_mut(), data.as_object()) {
+        for (key, value) in data {
+            if key != "aps" {
+                payload.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+use warp::http;
+use warp::hyper::body::Bytes;
+use warp::{Filter, Rejection};
+use crate::apiError::ApiError;
+use crate::webhooks::random_hex;
+use crate::StateStore;
+
+/// How far a request's timestamp may be from our clock before it counts as a replay.
+const MAX_CLOCK_SKEW_SECS: i64 = 5 * 60;
+
+/// Returned once when an install registers; the secret is never shown again.
+#[derive(Serialize, Debug)]
+pub struct InstallCreated {
+    install_id: String,
+    secret: String,
+}
+
+/// The per-install secrets clients sign their requests with. A signature proves which install
+/// sent a request, not that the install is well-behaved; the rate limits deal with that.
+#[derive(Debug, Clone, Default)]
+pub struct InstallRegistry {
+    installs: Arc<RwLock<HashMap<String, Install>>>,
+    /// signatures already accepted, with when their timestamp stops being accepted anyway
+    seen: Arc<Mutex<HashMap<Vec<u8>, i64>>>,
+}
+
+#[derive(Debug, Clone)]
+struct Install {
+    secret: String,
+    last_used: DateTime<Utc>,
+}
+
+impl InstallRegistry {
+    pub async fn register(&self) -> InstallCreated {
+        let created = InstallCreated {
+            install_id: random_hex(16),
+            secret: random_hex(32),
+        };
+
+        tracing::info!(install_id = created.install_id, "registering install");
+        self.installs.write().await.insert(created.install_id.clone(), Install {
+            secret: created.secret.clone(),
+            last_used: Utc::now(),
+        });
+
+        created
+    }
+
+    /// Every install's secret, by install ID, to keep between restarts.
+    pub async fn snapshot(&self) -> HashMap<String, String> {
+        self.installs.read().await.iter()
+            .map(|(install_id, install)| (install_id.clone(), install.secret.clone()))
+            .collect()
+    }
+
+    /// When each install last signed a request, to keep alongside `snapshot`.
+    pub async fn last_used(&self) -> HashMap<String, DateTime<Utc>> {
+        self.installs.read().await.iter()
+            .map(|(install_id, install)| (install_id.clone(), install.last_used))
+            .collect()
+    }
+
+    /// Put installs back; those with no recorded use count as used now.
+    pub async fn restore(&self, secrets: HashMap<String, String>, last_used: &HashMap<String, DateTime<Utc>>) {
+        let now = Utc::now();
+        self.installs.write().await.extend(secrets.into_iter().map(|(install_id, secret)| {
+            let last_used = last_used.get(&install_id).copied().unwrap_or(now);
+            (install_id, Install { secret, last_used })
+        }));
+    }
+
+    /// Forget installs unused since `cutoff`, except those in `keep` (which still own a
+    /// subscription or webhook). Returns how many were forgotten.
+    pub async fn remove_idle(&self, cutoff: DateTime<Utc>, keep: &HashSet<String>) -> usize {
+        let mut installs = self.installs.write().await;
+        let before = installs.len();
+        installs.retain(|install_id, install| install.last_used >= cutoff || keep.contains(install_id));
+        before - installs.len()
+    }
+
+    async fn secret(&self, install_id: &str) -> Option<String> {
+        self.installs.read().await.get(install_id).map(|install| install.secret.clone())
+    }
+
+    async fn used(&self, install_id: &str) {
+        if let Some(install) = self.installs.write().await.get_mut(install_id) {
+            install.last_used = Utc::now();
+        }
+    }
+
+    /// Note a verified signature, or say it was already used here: within the timestamp window
+    /// a captured request would otherwise replay. With a cluster store the signature is also
+    /// checked there, so it can't be replayed against another instance either.
+    fn first_use(&self, signature: &[u8], sent_at: i64) -> bool {
+        let now = Utc::now().timestamp();
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, expires| *expires >= now);
+        seen.insert(signature.to_vec(), sent_at + MAX_CLOCK_SKEW_SECS).is_none()
+    }
+}
+
+/// HMAC-SHA256 over `"{timestamp}.{METHOD}.{path}.{body}"`, as an install signs its requests.
+fn mac(secret: &str, timestamp: &str, method: &http::Method, path: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    for part in [timestamp.as_bytes(), method.as_str().as_bytes(), path.as_bytes()] {
+        mac.update(part);
+        mac.update(b".");
+    }
+    mac.update(body);
+    mac
+}
+
+/// The hex signature an install sends in `x-echoscope-signature` (after `sha256=`).
+#[cfg(test)]
+pub fn sign(secret: &str, timestamp: &str, method: &http::Method, path: &str, body: &[u8]) -> String {
+    hex::encode(mac(secret, timestamp, method, path, body).finalize().into_bytes())
+}
+
+/// The parts of a request its signature covers, apart from the body.
+struct SignedRequest {
+    method: http::Method,
+    path: warp::path::FullPath,
+    install: Option<String>,
+    timestamp: Option<String>,
+    signature: Option<String>,
+}
+
+fn signed_request() -> impl Filter<Extract = (SignedRequest,), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("x-echoscope-install"))
+        .and(warp::header::optional::<String>("x-echoscope-timestamp"))
+        .and(warp::header::optional::<String>("x-echoscope-signature"))
+        .map(|method, path, install, timestamp, signature| SignedRequest { method, path, install, timestamp, signature })
+}
+
+/// Who sent a mutating request: the install that signed it, or `None` for an unsigned
+/// request while `require_signed_requests` is off.
+async fn verify(request: SignedRequest, body: &[u8], store: &StateStore) -> Result<Option<String>, Rejection> {
+    let unauthorized = |message: &str| Rejection::from(ApiError::unauthorized(message));
+
+    let (install, timestamp, signature) = match (request.install, request.timestamp, request.signature) {
+        (Some(install), Some(timestamp), Some(signature)) => (install, timestamp, signature),
+        (None, None, None) if !store.config.require_signed_requests => return Ok(None),
+        _ => return Err(unauthorized("requests must be signed with x-echoscope-install, x-echoscope-timestamp and x-echoscope-signature")),
+    };
+
+    let sent_at: i64 = timestamp.parse().map_err(|_| unauthorized("x-echoscope-timestamp must be Unix seconds"))?;
+    if (chrono::Utc::now().timestamp() - sent_at).abs() > MAX_CLOCK_SKEW_SECS {
+        return Err(unauthorized("x-echoscope-timestamp is too far from the current time"));
+    }
+
+    // installs registered with another instance are only in the cluster store
+    let secret = match store.installs.secret(&install).await {
+        Some(secret) => secret,
+        None => {
+            let secret = store.cluster.install_secret(&install).await.ok_or_else(|| unauthorized("unknown install"))?;
+            store.installs.restore(HashMap::from([(install.clone(), secret.clone())]), &HashMap::new()).await;
+            secret
+        }
+    };
+    let signature = signature.strip_prefix("sha256=")
+        .and_then(|signature| hex::decode(signature).ok())
+        .ok_or_else(|| unauthorized("x-echoscope-signature must be sha256=<hex>"))?;
+
+    // `verify_slice` compares in constant time
+    mac(&secret, &timestamp, &request.method, request.path.as_str(), body)
+        .verify_slice(&signature)
+        .map_err(|_| unauthorized("signature does not match"))?;
+
+    let replayed = || unauthorized("this request has already been made; sign it again with a new timestamp");
+    if !store.installs.first_use(&signature, sent_at) {
+        return Err(replayed());
+    }
+    if !store.cluster.first_signature_use(&signature, sent_at + MAX_CLOCK_SKEW_SECS).await.map_err(crate::cluster::unavailable)? {
+        return Err(replayed());
+    }
+    store.installs.used(&install).await;
+    store.cluster.install_used(&install).await;
+
+    Ok(Some(install))
+}
+
+/// A signed request without a body, yielding the install that sent it.
+pub fn signed(store: StateStore) -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    signed_request()
+        .and(warp::any().map(move || store.clone()))
+        .and_then(|request, store: StateStore| async move { verify(request, b"", &store).await })
+}
+
+/// A signed request with a JSON body (up to 16 KiB), yielding the install that sent it and the body.
+pub fn signed_json<T: DeserializeOwned + Send>(store: StateStore) -> impl Filter<Extract = (Option<String>, T), Error = Rejection> + Clone {
+    signed_request()
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(warp::body::bytes())
+        .and(warp::any().map(move || store.clone()))
+        .and_then(|request, body: Bytes, store: StateStore| async move {
+            let install = verify(request, &body, &store).await?;
+            let value = serde_json::from_slice::<T>(&body)
+                .map_err(|e| ApiError::bad_request("invalid_body", e.to_string()))?;
+
+            Ok::<_, Rejection>((install, value))
+        })
+        .untuple_one()
+}
+
+/// `POST /v1/installs`: hand a new install the secret it signs with from now on.
+pub async fn register_install(state_store: StateStore) -> Result<impl warp::Reply, warp::Rejection> {
+    let created = state_store.installs.register().await;
+    state_store.cluster.share_install(&created.install_id, &created.secret).await;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&created),
+        http::StatusCode::CREATED,
+    ))
+}
@@ -0,0 +1,866 @@
+use chrono::{DateTime, Utc};
+use robotevents::schema::Match;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::Debug;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use warp::http;
+use crate::apiError::ApiError;
+use crate::config::ClusterSettings;
+use crate::webhooks::StoredWebhook;
+use crate::{CompetitionDivisionPair, TeamTokenPair};
+
+pub type ClusterError = Box<dyn Error + Send + Sync>;
+
+pub type Subscriptions = HashMap<CompetitionDivisionPair, Vec<TeamTokenPair>>;
+
+/// State shared by every instance of the backend: subscriptions, install secrets, webhooks,
+/// the match list each division was last pushed from, and who polls which division.
+pub trait ClusterStore: Send + Sync + Debug {
+    /// Record that `instance` is alive and return how many instances have been seen within `ttl`.
+    fn heartbeat(&self, instance: &str, now: DateTime<Utc>, ttl: Duration) -> Result<usize, ClusterError>;
+
+    /// The divisions `instance` holds an unexpired lease on.
+    fn leases(&self, instance: &str, now: DateTime<Utc>) -> Result<Vec<CompetitionDivisionPair>, ClusterError>;
+
+    /// Take or renew the lease on a division until `expires_at`. Fails (returns false) while
+    /// another instance's lease is unexpired.
+    fn acquire(&self, division: &CompetitionDivisionPair, instance: &str, now: DateTime<Utc>, expires_at: DateTime<Utc>) -> Result<bool, ClusterError>;
+
+    fn release(&self, division: &CompetitionDivisionPair, instance: &str) -> Result<(), ClusterError>;
+
+    fn release_all(&self, instance: &str) -> Result<(), ClusterError>;
+
+    fn subscriptions(&self) -> Result<Subscriptions, ClusterError>;
+
+    /// Change the shared subscriptions in one transaction; `modify` sees them as they stand.
+    fn modify_subscriptions(&self, modify: &mut dyn FnMut(&mut Subscriptions)) -> Result<(), ClusterError>;
+
+    fn matches(&self, division: &CompetitionDivisionPair) -> Result<Option<Vec<Match>>, ClusterError>;
+
+    fn put_matches(&self, division: &CompetitionDivisionPair, matches: &[Match]) -> Result<(), ClusterError>;
+
+    fn install_secret(&self, install_id: &str) -> Result<Option<String>, ClusterError>;
+
+    fn put_install(&self, install_id: &str, secret: &str, now: DateTime<Utc>) -> Result<(), ClusterError>;
+
+    /// Note that an install signed a request at `now`.
+    fn install_used(&self, install_id: &str, now: DateTime<Utc>) -> Result<(), ClusterError>;
+
+    /// Forget installs no instance has seen used since `cutoff`, except those in `keep`.
+    fn remove_idle_installs(&self, cutoff: DateTime<Utc>, keep: &HashSet<String>) -> Result<usize, ClusterError>;
+
+    /// Note a request signature as used until `expires_at` (Unix seconds), or say some
+    /// instance already accepted it. Signatures expired by `now` are forgotten.
+    fn first_signature_use(&self, signature: &[u8], expires_at: i64, now: i64) -> Result<bool, ClusterError>;
+
+    fn webhooks(&self) -> Result<Vec<StoredWebhook>, ClusterError>;
+
+    /// Add a webhook, or replace the one with its ID.
+    fn put_webhook(&self, webhook: &StoredWebhook) -> Result<(), ClusterError>;
+
+    fn remove_webhook(&self, id: &str) -> Result<(), ClusterError>;
+}
+
+/// An in-process stand-in for a shared store, for running several `StateStore`s in one test.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MemoryCluster {
+    state: Mutex<MemoryState>,
+}
+
+#[cfg(test)]
+#[derive(Debug, Default)]
+struct MemoryState {
+    instances: HashMap<String, DateTime<Utc>>,
+    leases: HashMap<CompetitionDivisionPair, (String, DateTime<Utc>)>,
+    subscriptions: Subscriptions,
+    matches: HashMap<CompetitionDivisionPair, Vec<Match>>,
+    /// install ID to secret and when it was last used
+    installs: HashMap<String, (String, DateTime<Utc>)>,
+    /// request signature to when it stops being accepted anyway
+    signatures: HashMap<Vec<u8>, i64>,
+    webhooks: HashMap<String, StoredWebhook>,
+}
+
+#[cfg(test)]
+impl ClusterStore for MemoryCluster {
+    fn heartbeat(&self, instance: &str, now: DateTime<Utc>, ttl: Duration) -> Result<usize, ClusterError> {
+        let mut state = self.state.lock().unwrap();
+        state.instances.insert(instance.to_string(), now);
+        let since = now - chrono::Duration::from_std(ttl)?;
+        Ok(state.instances.values().filter(|seen| **seen > since).count())
+    }
+
+    fn leases(&self, instance: &str, now: DateTime<Utc>) -> Result<Vec<CompetitionDivisionPair>, ClusterError> {
+        Ok(self.state.lock().unwrap().leases.iter()
+            .filter(|(_, (owner, expires_at))| owner == instance && *expires_at > now)
+            .map(|(division, _)| division.clone())
+            .collect())
+    }
+
+    fn acquire(&self, division: &CompetitionDivisionPair, instance: &str, now: DateTime<Utc>, expires_at: DateTime<Utc>) -> Result<bool, ClusterError> {
+        let mut state = self.state.lock().unwrap();
+        let free = state.leases.get(division).is_none_or(|(owner, expires)| owner == instance || *expires <= now);
+        if free {
+            state.leases.insert(division.clone(), (instance.to_string(), expires_at));
+        }
+        Ok(free)
+    }
+
+    fn release(&self, division: &CompetitionDivisionPair, instance: &str) -> Result<(), ClusterError> {
+        let mut state = self.state.lock().unwrap();
+        if state.leases.get(division).is_some_and(|(owner, _)| owner == instance) {
+            state.leases.remove(division);
+        }
+        Ok(())
+    }
+
+    fn release_all(&self, instance: &str) -> Result<(), ClusterError> {
+        self.state.lock().unwrap().leases.retain(|_, (owner, _)| owner != instance);
+        Ok(())
+    }
+
+    fn subscriptions(&self) -> Result<Subscriptions, ClusterError> {
+        Ok(self.state.lock().unwrap().subscriptions.clone())
+    }
+
+    fn modify_subscriptions(&self, modify: &mut dyn FnMut(&mut Subscriptions)) -> Result<(), ClusterError> {
+        modify(&mut self.state.lock().unwrap().subscriptions);
+        Ok(())
+    }
+
+    fn matches(&self, division: &CompetitionDivisionPair) -> Result<Option<Vec<Match>>, ClusterError> {
+        Ok(self.state.lock().unwrap().matches.get(division).cloned())
+    }
+
+    fn put_matches(&self, division: &CompetitionDivisionPair, matches: &[Match]) -> Result<(), ClusterError> {
+        self.state.lock().unwrap().matches.insert(division.clone(), matches.to_vec());
+        Ok(())
+    }
+
+    fn install_secret(&self, install_id: &str) -> Result<Option<String>, ClusterError> {
+        Ok(self.state.lock().unwrap().installs.get(install_id).map(|(secret, _)| secret.clone()))
+    }
+
+    fn put_install(&self, install_id: &str, secret: &str, now: DateTime<Utc>) -> Result<(), ClusterError> {
+        self.state.lock().unwrap().installs.insert(install_id.to_string(), (secret.to_string(), now));
+        Ok(())
+    }
+
+    fn install_used(&self, install_id: &str, now: DateTime<Utc>) -> Result<(), ClusterError> {
+        if let Some((_, last_used)) = self.state.lock().unwrap().installs.get_mut(install_id) {
+            *last_used = now;
+        }
+        Ok(())
+    }
+
+    fn remove_idle_installs(&self, cutoff: DateTime<Utc>, keep: &HashSet<String>) -> Result<usize, ClusterError> {
+        let installs = &mut self.state.lock().unwrap().installs;
+        let before = installs.len();
+        installs.retain(|install_id, (_, last_used)| *last_used >= cutoff || keep.contains(install_id));
+        Ok(before - installs.len())
+    }
+
+    fn first_signature_use(&self, signature: &[u8], expires_at: i64, now: i64) -> Result<bool, ClusterError> {
+        let signatures = &mut self.state.lock().unwrap().signatures;
+        signatures.retain(|_, expires| *expires >= now);
+        Ok(signatures.insert(signature.to_vec(), expires_at).is_none())
+    }
+
+    fn webhooks(&self) -> Result<Vec<StoredWebhook>, ClusterError> {
+        Ok(self.state.lock().unwrap().webhooks.values().cloned().collect())
+    }
+
+    fn put_webhook(&self, webhook: &StoredWebhook) -> Result<(), ClusterError> {
+        self.state.lock().unwrap().webhooks.insert(webhook.id.clone(), webhook.clone());
+        Ok(())
+    }
+
+    fn remove_webhook(&self, id: &str) -> Result<(), ClusterError> {
+        self.state.lock().unwrap().webhooks.remove(id);
+        Ok(())
+    }
+}
+
+/// A SQLite file every instance opens, for running several processes on one machine (or on
+/// anything sharing a filesystem SQLite can lock).
+#[derive(Debug)]
+pub struct SqliteCluster {
+    connection: Mutex<Connection>,
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS instances (
+        instance TEXT PRIMARY KEY,
+        seen_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS leases (
+        competition_id INTEGER NOT NULL,
+        division_id INTEGER NOT NULL,
+        owner TEXT NOT NULL,
+        expires_at INTEGER NOT NULL,
+        PRIMARY KEY (competition_id, division_id)
+    );
+    CREATE TABLE IF NOT EXISTS subscriptions (
+        competition_id INTEGER NOT NULL,
+        division_id INTEGER NOT NULL,
+        device_token TEXT NOT NULL,
+        subscription TEXT NOT NULL,
+        PRIMARY KEY (competition_id, division_id, device_token)
+    );
+    CREATE TABLE IF NOT EXISTS matches (
+        competition_id INTEGER NOT NULL,
+        division_id INTEGER NOT NULL,
+        matches TEXT NOT NULL,
+        PRIMARY KEY (competition_id, division_id)
+    );
+    CREATE TABLE IF NOT EXISTS installs (
+        install_id TEXT PRIMARY KEY,
+        secret TEXT NOT NULL,
+        last_used INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS webhooks (
+        id TEXT PRIMARY KEY,
+        webhook TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS signatures (
+        signature BLOB PRIMARY KEY,
+        expires_at INTEGER NOT NULL
+    );
+";
+
+impl SqliteCluster {
+    pub fn open(path: &Path) -> Result<Self, ClusterError> {
+        let connection = Connection::open(path)?;
+        // other instances hold the write lock for a transaction at most
+        connection.busy_timeout(Duration::from_secs(5))?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(SqliteCluster {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn read_subscriptions(connection: &Connection) -> Result<Subscriptions, ClusterError> {
+        let mut statement = connection.prepare("SELECT competition_id, division_id, subscription FROM subscriptions ORDER BY rowid")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?)))?;
+
+        let mut subscriptions = Subscriptions::new();
+        for row in rows {
+            let (competition_id, division_id, subscription) = row?;
+            subscriptions.entry(CompetitionDivisionPair::new(competition_id, division_id))
+                .or_default()
+                .push(serde_json::from_str(&subscription)?);
+        }
+        Ok(subscriptions)
+    }
+}
+
+/// Each division's subscriptions as stored, to tell which divisions a change touched.
+fn encode(subscriptions: &Subscriptions) -> Result<HashMap<CompetitionDivisionPair, Vec<(String, String)>>, serde_json::Error> {
+    subscriptions.iter()
+        .map(|(division, devices)| {
+            let rows = devices.iter()
+                .map(|pair| Ok((pair.device_token.clone(), serde_json::to_string(pair)?)))
+                .collect::<Result<_, serde_json::Error>>()?;
+            Ok((division.clone(), rows))
+        })
+        .collect()
+}
+
+impl ClusterStore for SqliteCluster {
+    fn heartbeat(&self, instance: &str, now: DateTime<Utc>, ttl: Duration) -> Result<usize, ClusterError> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO instances (instance, seen_at) VALUES (?1, ?2)
+             ON CONFLICT (instance) DO UPDATE SET seen_at = excluded.seen_at",
+            params![instance, now.timestamp_millis()],
+        )?;
+        // forget instances long gone so the table doesn't grow with every deploy
+        connection.execute("DELETE FROM instances WHERE seen_at < ?1", params![(now - chrono::Duration::days(1)).timestamp_millis()])?;
+
+        let since = (now - chrono::Duration::from_std(ttl)?).timestamp_millis();
+        let live: i64 = connection.query_row("SELECT COUNT(*) FROM instances WHERE seen_at > ?1", params![since], |row| row.get(0))?;
+        Ok(live as usize)
+    }
+
+    fn leases(&self, instance: &str, now: DateTime<Utc>) -> Result<Vec<CompetitionDivisionPair>, ClusterError> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT competition_id, division_id FROM leases WHERE owner = ?1 AND expires_at > ?2")?;
+        let leases = statement
+            .query_map(params![instance, now.timestamp_millis()], |row| Ok(CompetitionDivisionPair::new(row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(leases)
+    }
+
+    fn acquire(&self, division: &CompetitionDivisionPair, instance: &str, now: DateTime<Utc>, expires_at: DateTime<Utc>) -> Result<bool, ClusterError> {
+        let changed = self.connection.lock().unwrap().execute(
+            "INSERT INTO leases (competition_id, division_id, owner, expires_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (competition_id, division_id) DO UPDATE SET owner = excluded.owner, expires_at = excluded.expires_at
+             WHERE leases.owner = excluded.owner OR leases.expires_at <= ?5",
+            params![division.competition_id, division.division_id, instance, expires_at.timestamp_millis(), now.timestamp_millis()],
+        )?;
+        Ok(changed == 1)
+    }
+
+    fn release(&self, division: &CompetitionDivisionPair, instance: &str) -> Result<(), ClusterError> {
+        self.connection.lock().unwrap().execute(
+            "DELETE FROM leases WHERE competition_id = ?1 AND division_id = ?2 AND owner = ?3",
+            params![division.competition_id, division.division_id, instance],
+        )?;
+        Ok(())
+    }
+
+    fn release_all(&self, instance: &str) -> Result<(), ClusterError> {
+        self.connection.lock().unwrap().execute("DELETE FROM leases WHERE owner = ?1", params![instance])?;
+        Ok(())
+    }
+
+    fn subscriptions(&self) -> Result<Subscriptions, ClusterError> {
+        Self::read_subscriptions(&self.connection.lock().unwrap())
+    }
+
+    fn modify_subscriptions(&self, modify: &mut dyn FnMut(&mut Subscriptions)) -> Result<(), ClusterError> {
+        let mut connection = self.connection.lock().unwrap();
+        // take the write lock up front so no other instance changes anything in between
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let mut subscriptions = Self::read_subscriptions(&transaction)?;
+        let before = encode(&subscriptions)?;
+        modify(&mut subscriptions);
+        let after = encode(&subscriptions)?;
+
+        let touched = before.keys().chain(after.keys())
+            .filter(|division| before.get(division) != after.get(division))
+            .collect::<HashSet<_>>();
+        for division in touched {
+            transaction.execute(
+                "DELETE FROM subscriptions WHERE competition_id = ?1 AND division_id = ?2",
+                params![division.competition_id, division.division_id],
+            )?;
+            for (device_token, subscription) in after.get(division).into_iter().flatten() {
+                transaction.execute(
+                    "INSERT OR REPLACE INTO subscriptions (competition_id, division_id, device_token, subscription) VALUES (?1, ?2, ?3, ?4)",
+                    params![division.competition_id, division.division_id, device_token, subscription],
+                )?;
+            }
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn matches(&self, division: &CompetitionDivisionPair) -> Result<Option<Vec<Match>>, ClusterError> {
+        let matches: Option<String> = self.connection.lock().unwrap()
+            .query_row(
+                "SELECT matches FROM matches WHERE competition_id = ?1 AND division_id = ?2",
+                params![division.competition_id, division.division_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(matches.map(|matches| serde_json::from_str(&matches)).transpose()?)
+    }
+
+    fn put_matches(&self, division: &CompetitionDivisionPair, matches: &[Match]) -> Result<(), ClusterError> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO matches (competition_id, division_id, matches) VALUES (?1, ?2, ?3)",
+            params![division.competition_id, division.division_id, serde_json::to_string(matches)?],
+        )?;
+        Ok(())
+    }
+
+    fn install_secret(&self, install_id: &str) -> Result<Option<String>, ClusterError> {
+        Ok(self.connection.lock().unwrap()
+            .query_row("SELECT secret FROM installs WHERE install_id = ?1", params![install_id], |row| row.get(0))
+            .optional()?)
+    }
+
+    fn put_install(&self, install_id: &str, secret: &str, now: DateTime<Utc>) -> Result<(), ClusterError> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO installs (install_id, secret, last_used) VALUES (?1, ?2, ?3)",
+            params![install_id, secret, now.timestamp_millis()],
+        )?;
+        Ok(())
+    }
+
+    fn install_used(&self, install_id: &str, now: DateTime<Utc>) -> Result<(), ClusterError> {
+        self.connection.lock().unwrap().execute(
+            "UPDATE installs SET last_used = MAX(last_used, ?2) WHERE install_id = ?1",
+            params![install_id, now.timestamp_millis()],
+        )?;
+        Ok(())
+    }
+
+    fn remove_idle_installs(&self, cutoff: DateTime<Utc>, keep: &HashSet<String>) -> Result<usize, ClusterError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let idle: Vec<String> = transaction
+            .prepare("SELECT install_id FROM installs WHERE last_used < ?1")?
+            .query_map(params![cutoff.timestamp_millis()], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        let mut removed = 0;
+        for install_id in idle.iter().filter(|install_id| !keep.contains(*install_id)) {
+            removed += transaction.execute("DELETE FROM installs WHERE install_id = ?1", params![install_id])?;
+        }
+
+        transaction.commit()?;
+        Ok(removed)
+    }
+
+    fn first_signature_use(&self, signature: &[u8], expires_at: i64, now: i64) -> Result<bool, ClusterError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        transaction.execute("DELETE FROM signatures WHERE expires_at < ?1", params![now])?;
+        let inserted = transaction.execute(
+            "INSERT OR IGNORE INTO signatures (signature, expires_at) VALUES (?1, ?2)",
+            params![signature, expires_at],
+        )?;
+
+        transaction.commit()?;
+        Ok(inserted == 1)
+    }
+
+    fn webhooks(&self) -> Result<Vec<StoredWebhook>, ClusterError> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT webhook FROM webhooks ORDER BY rowid")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+
+        let mut webhooks = Vec::new();
+        for row in rows {
+            webhooks.push(serde_json::from_str(&row?)?);
+        }
+        Ok(webhooks)
+    }
+
+    fn put_webhook(&self, webhook: &StoredWebhook) -> Result<(), ClusterError> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO webhooks (id, webhook) VALUES (?1, ?2) ON CONFLICT (id) DO UPDATE SET webhook = excluded.webhook",
+            params![webhook.id, serde_json::to_string(webhook)?],
+        )?;
+        Ok(())
+    }
+
+    fn remove_webhook(&self, id: &str) -> Result<(), ClusterError> {
+        self.connection.lock().unwrap().execute("DELETE FROM webhooks WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+}
+
+/// How long a client should wait to retry a change the cluster store couldn't take.
+const STORE_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// The answer to a change the cluster store couldn't take. Nothing was saved, so the client
+/// should try again.
+pub fn unavailable(_: ClusterError) -> ApiError {
+    ApiError::new(http::StatusCode::SERVICE_UNAVAILABLE, "store_unavailable", "unable to save the change right now")
+        .with_retry_after(STORE_RETRY_AFTER)
+}
+
+/// What a claim came to: the divisions this instance polls and pushes for this cycle, and
+/// which of those it just took over.
+#[derive(Debug, Default, PartialEq)]
+pub struct Claimed {
+    pub owned: Vec<CompetitionDivisionPair>,
+    pub acquired: Vec<CompetitionDivisionPair>,
+}
+
+/// This instance's view of the cluster. Without a store it's the only instance and owns
+/// every division; with one, each instance leases its fair share of the subscribed
+/// divisions and picks up those whose owner stopped renewing.
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    store: Option<Arc<dyn ClusterStore>>,
+    instance_id: String,
+    lease: Duration,
+    /// divisions leased at the last claim
+    owned: Arc<Mutex<HashSet<CompetitionDivisionPair>>>,
+}
+
+impl Default for Cluster {
+    fn default() -> Self {
+        Cluster {
+            store: None,
+            instance_id: "local".to_string(),
+            lease: Duration::ZERO,
+            owned: Arc::default(),
+        }
+    }
+}
+
+impl Cluster {
+    pub fn new(store: Arc<dyn ClusterStore>, instance_id: impl Into<String>, lease: Duration) -> Self {
+        Cluster {
+            store: Some(store),
+            instance_id: instance_id.into(),
+            lease,
+            owned: Arc::default(),
+        }
+    }
+
+    pub fn from_config(settings: &ClusterSettings) -> Result<Self, Box<dyn Error>> {
+        let Some(path) = &settings.store_path else {
+            return Ok(Cluster::default());
+        };
+
+        let store = SqliteCluster::open(path)
+            .map_err(|e| format!("Unable to open cluster store {}: {}", path.display(), e))?;
+        let instance_id = settings.instance_id.clone().unwrap_or_else(|| crate::webhooks::random_hex(8));
+        tracing::info!(instance_id, store = %path.display(), "joining cluster");
+
+        Ok(Cluster::new(Arc::new(store), instance_id, Duration::from_secs(settings.lease_secs)))
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Whether this instance pushes for `division`. Always, without a cluster store.
+    pub fn owns(&self, division: &CompetitionDivisionPair) -> bool {
+        self.store.is_none() || self.owned.lock().unwrap().contains(division)
+    }
+
+    /// Run `call` against the store on the blocking pool: SQLite can wait seconds for another
+    /// instance's lock, which mustn't hold up a runtime worker thread.
+    async fn blocking<T: Send + 'static>(
+        store: &Arc<dyn ClusterStore>,
+        call: impl FnOnce(&dyn ClusterStore) -> Result<T, ClusterError> + Send + 'static,
+    ) -> Result<T, ClusterError> {
+        let store = store.clone();
+        tokio::task::spawn_blocking(move || call(store.as_ref())).await?
+    }
+
+    /// Renew this instance's leases and take free ones, up to its share of `divisions` (the
+    /// subscribed divisions). Leases past that share, or on divisions nobody subscribes to
+    /// any more, are released for other instances to pick up.
+    pub async fn claim(&self, divisions: &[CompetitionDivisionPair], now: DateTime<Utc>) -> Claimed {
+        let Some(store) = &self.store else {
+            return Claimed { owned: divisions.to_vec(), acquired: Vec::new() };
+        };
+
+        let (instance_id, lease, divisions) = (self.instance_id.clone(), self.lease, divisions.to_vec());
+        let claimed = Self::blocking(store, move |store| {
+            let instances = store.heartbeat(&instance_id, now, lease)?.max(1);
+            let share = divisions.len().div_ceil(instances);
+            let expires_at = now + chrono::Duration::from_std(lease)?;
+
+            let mut claimed = Claimed::default();
+            for division in store.leases(&instance_id, now)? {
+                if divisions.contains(&division) && claimed.owned.len() < share && store.acquire(&division, &instance_id, now, expires_at)? {
+                    claimed.owned.push(division);
+                } else {
+                    store.release(&division, &instance_id)?;
+                }
+            }
+
+            for division in &divisions {
+                if claimed.owned.len() >= share {
+                    break;
+                }
+                if !claimed.owned.contains(division) && store.acquire(division, &instance_id, now, expires_at)? {
+                    claimed.owned.push(division.clone());
+                    claimed.acquired.push(division.clone());
+                }
+            }
+
+            Ok(claimed)
+        })
+        .await;
+
+        let claimed = claimed.unwrap_or_else(|e| {
+            // pushing without a lease risks duplicates; sit this cycle out instead
+            tracing::error!(error = %e, "unable to claim divisions");
+            Claimed::default()
+        });
+
+        let mut owned = self.owned.lock().unwrap();
+        *owned = claimed.owned.iter().cloned().collect();
+        if !claimed.acquired.is_empty() {
+            tracing::info!(acquired = ?claimed.acquired, owned = owned.len(), "took over divisions");
+        }
+        claimed
+    }
+
+    /// Give up every lease, so other instances take over without waiting for them to expire.
+    pub async fn release_all(&self) {
+        let Some(store) = &self.store else { return };
+        self.owned.lock().unwrap().clear();
+        let instance_id = self.instance_id.clone();
+        if let Err(e) = Self::blocking(store, move |store| store.release_all(&instance_id)).await {
+            tracing::error!(error = %e, "unable to release leases");
+        }
+    }
+
+    /// Replace `local` with the shared subscriptions.
+    pub async fn sync_subscriptions(&self, local: &mut Subscriptions) {
+        let Some(store) = &self.store else { return };
+        match Self::blocking(store, |store| store.subscriptions()).await {
+            Ok(shared) => *local = shared,
+            Err(e) => tracing::error!(error = %e, "unable to read shared subscriptions"),
+        }
+    }
+
+    /// Run `modify` on the shared subscriptions and leave `local` matching them. If the store
+    /// can't be reached nothing changes, here or there, and the error is returned.
+    pub async fn modify_subscriptions<R: Send + 'static>(
+        &self,
+        local: &mut Subscriptions,
+        modify: impl FnOnce(&mut Subscriptions) -> R + Send + 'static,
+    ) -> Result<R, ClusterError> {
+        let Some(store) = &self.store else {
+            return Ok(modify(local));
+        };
+
+        let (result, shared) = Self::blocking(store, move |store| {
+            let mut modify = Some(modify);
+            let mut modified = None;
+            store.modify_subscriptions(&mut |shared| {
+                if let Some(modify) = modify.take() {
+                    modified = Some((modify(shared), shared.clone()));
+                }
+            })?;
+            modified.ok_or_else(|| "the store committed without running the change".into())
+        })
+        .await
+        .inspect_err(|e| tracing::error!(error = %e, "unable to update shared subscriptions"))?;
+
+        *local = shared;
+        Ok(result)
+    }
+
+    /// The match list the division's owner last pushed from.
+    pub async fn shared_matches(&self, division: &CompetitionDivisionPair) -> Option<Vec<Match>> {
+        let store = self.store.as_ref()?;
+        let shared = division.clone();
+        Self::blocking(store, move |store| store.matches(&shared)).await.unwrap_or_else(|e| {
+            tracing::error!(?division, error = %e, "unable to read shared matches");
+            None
+        })
+    }
+
+    pub async fn share_matches(&self, division: &CompetitionDivisionPair, matches: &[Match]) {
+        let Some(store) = &self.store else { return };
+        let (shared, matches) = (division.clone(), matches.to_vec());
+        if let Err(e) = Self::blocking(store, move |store| store.put_matches(&shared, &matches)).await {
+            tracing::error!(?division, error = %e, "unable to share matches");
+        }
+    }
+
+    pub async fn install_secret(&self, install_id: &str) -> Option<String> {
+        let store = self.store.as_ref()?;
+        let install_id = install_id.to_string();
+        Self::blocking(store, move |store| store.install_secret(&install_id)).await.unwrap_or_else(|e| {
+            tracing::error!(error = %e, "unable to read shared install");
+            None
+        })
+    }
+
+    pub async fn share_install(&self, install_id: &str, secret: &str) {
+        let Some(store) = &self.store else { return };
+        let (install_id, secret) = (install_id.to_string(), secret.to_string());
+        if let Err(e) = Self::blocking(store, move |store| store.put_install(&install_id, &secret, Utc::now())).await {
+            tracing::error!(error = %e, "unable to share install");
+        }
+    }
+
+    /// Note that an install was used here, so other instances don't forget it as idle.
+    pub async fn install_used(&self, install_id: &str) {
+        let Some(store) = &self.store else { return };
+        let install_id = install_id.to_string();
+        if let Err(e) = Self::blocking(store, move |store| store.install_used(&install_id, Utc::now())).await {
+            tracing::error!(error = %e, "unable to record shared install use");
+        }
+    }
+
+    /// Forget installs idle since `cutoff` everywhere, except those in `keep`, so they can't
+    /// sign requests to any instance.
+    pub async fn remove_idle_installs(&self, cutoff: DateTime<Utc>, keep: &HashSet<String>) {
+        let Some(store) = &self.store else { return };
+        let keep = keep.clone();
+        match Self::blocking(store, move |store| store.remove_idle_installs(cutoff, &keep)).await {
+            Ok(removed) if removed > 0 => tracing::info!(removed, "forgot idle shared installs"),
+            Ok(_) => {}
+            Err(e) => tracing::error!(error = %e, "unable to forget idle shared installs"),
+        }
+    }
+
+    /// Note a verified request signature as used on every instance, or say one already
+    /// accepted it. Without a store the caller's own replay cache is all there is.
+    pub async fn first_signature_use(&self, signature: &[u8], expires_at: i64) -> Result<bool, ClusterError> {
+        let Some(store) = &self.store else { return Ok(true) };
+        let signature = signature.to_vec();
+        Self::blocking(store, move |store| store.first_signature_use(&signature, expires_at, Utc::now().timestamp()))
+            .await
+            .inspect_err(|e| tracing::error!(error = %e, "unable to record shared request signature"))
+    }
+
+    /// Every webhook registered with any instance, or `None` without a cluster store.
+    pub async fn shared_webhooks(&self) -> Option<Result<Vec<StoredWebhook>, ClusterError>> {
+        let store = self.store.as_ref()?;
+        Some(Self::blocking(store, |store| store.webhooks()).await)
+    }
+
+    pub async fn share_webhook(&self, webhook: StoredWebhook) -> Result<(), ClusterError> {
+        let Some(store) = &self.store else { return Ok(()) };
+        Self::blocking(store, move |store| store.put_webhook(&webhook)).await
+    }
+
+    pub async fn remove_shared_webhook(&self, id: &str) -> Result<(), ClusterError> {
+        let Some(store) = &self.store else { return Ok(()) };
+        let id = id.to_string();
+        Self::blocking(store, move |store| store.remove_webhook(&id)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn divisions(count: i32) -> Vec<CompetitionDivisionPair> {
+        (1..=count).map(|division_id| CompetitionDivisionPair::new(1, division_id)).collect()
+    }
+
+    async fn split_and_fail_over(store: Arc<dyn ClusterStore>) {
+        let lease = Duration::from_secs(90);
+        let a = Cluster::new(store.clone(), "a", lease);
+        let b = Cluster::new(store, "b", lease);
+        let divisions = divisions(4);
+        let start = Utc::now();
+        let later = |secs: i64| start + chrono::Duration::seconds(secs);
+
+        // alone, a takes everything
+        assert_eq!(a.claim(&divisions, later(0)).await.owned.len(), 4);
+
+        // b joins: a gives up half on its next claim and b picks them up
+        assert!(b.claim(&divisions, later(1)).await.owned.is_empty());
+        assert_eq!(a.claim(&divisions, later(30)).await.owned.len(), 2);
+        let b_claimed = b.claim(&divisions, later(31)).await;
+        assert_eq!(b_claimed.owned.len(), 2);
+        assert_eq!(b_claimed.acquired, b_claimed.owned);
+        assert!(divisions.iter().all(|division| a.owns(division) != b.owns(division)));
+
+        // a stops renewing; once its leases lapse b takes over the lot
+        assert_eq!(b.claim(&divisions, later(61)).await.owned.len(), 2);
+        assert_eq!(b.claim(&divisions, later(150)).await.owned.len(), 4);
+
+        // a comes back, finds its leases gone, and waits for b to shed some
+        assert!(a.claim(&divisions, later(151)).await.owned.is_empty());
+        b.release_all().await;
+        assert_eq!(a.claim(&divisions, later(152)).await.owned.len(), 2);
+    }
+
+    /// A store that's gone away, or (with `panics`) one whose calls blow up.
+    #[derive(Debug)]
+    struct Unreachable {
+        panics: bool,
+    }
+
+    impl Unreachable {
+        fn fail<T>(&self) -> Result<T, ClusterError> {
+            if self.panics {
+                panic!("store call panicked");
+            }
+            Err("database is locked".into())
+        }
+    }
+
+    impl ClusterStore for Unreachable {
+        fn heartbeat(&self, _: &str, _: DateTime<Utc>, _: Duration) -> Result<usize, ClusterError> { self.fail() }
+        fn leases(&self, _: &str, _: DateTime<Utc>) -> Result<Vec<CompetitionDivisionPair>, ClusterError> { self.fail() }
+        fn acquire(&self, _: &CompetitionDivisionPair, _: &str, _: DateTime<Utc>, _: DateTime<Utc>) -> Result<bool, ClusterError> { self.fail() }
+        fn release(&self, _: &CompetitionDivisionPair, _: &str) -> Result<(), ClusterError> { self.fail() }
+        fn release_all(&self, _: &str) -> Result<(), ClusterError> { self.fail() }
+        fn subscriptions(&self) -> Result<Subscriptions, ClusterError> { self.fail() }
+        fn modify_subscriptions(&self, _: &mut dyn FnMut(&mut Subscriptions)) -> Result<(), ClusterError> { self.fail() }
+        fn matches(&self, _: &CompetitionDivisionPair) -> Result<Option<Vec<Match>>, ClusterError> { self.fail() }
+        fn put_matches(&self, _: &CompetitionDivisionPair, _: &[Match]) -> Result<(), ClusterError> { self.fail() }
+        fn install_secret(&self, _: &str) -> Result<Option<String>, ClusterError> { self.fail() }
+        fn put_install(&self, _: &str, _: &str, _: DateTime<Utc>) -> Result<(), ClusterError> { self.fail() }
+        fn install_used(&self, _: &str, _: DateTime<Utc>) -> Result<(), ClusterError> { self.fail() }
+        fn remove_idle_installs(&self, _: DateTime<Utc>, _: &HashSet<String>) -> Result<usize, ClusterError> { self.fail() }
+        fn first_signature_use(&self, _: &[u8], _: i64, _: i64) -> Result<bool, ClusterError> { self.fail() }
+        fn webhooks(&self) -> Result<Vec<StoredWebhook>, ClusterError> { self.fail() }
+        fn put_webhook(&self, _: &StoredWebhook) -> Result<(), ClusterError> { self.fail() }
+        fn remove_webhook(&self, _: &str) -> Result<(), ClusterError> { self.fail() }
+    }
+
+    #[tokio::test]
+    async fn changes_the_store_cannot_take_are_not_made() {
+        for panics in [false, true] {
+            let cluster = Cluster::new(Arc::new(Unreachable { panics }), "a", Duration::from_secs(90));
+            let mut local = Subscriptions::new();
+
+            let outcome = cluster.modify_subscriptions(&mut local, |subscriptions| {
+                subscriptions.insert(CompetitionDivisionPair::new(1, 1), Vec::new());
+            })
+            .await;
+
+            assert!(outcome.is_err());
+            assert!(local.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn leases_split_divisions_and_fail_over() {
+        split_and_fail_over(Arc::new(MemoryCluster::default())).await;
+    }
+
+    #[tokio::test]
+    async fn sqlite_store_is_shared_between_connections() {
+        let path = std::env::temp_dir().join(format!("echoscope-cluster-{}.sqlite", crate::webhooks::random_hex(8)));
+        split_and_fail_over(Arc::new(SqliteCluster::open(&path).unwrap())).await;
+
+        let a = SqliteCluster::open(&path).unwrap();
+        let b = SqliteCluster::open(&path).unwrap();
+        let now = Utc::now();
+        a.put_install("install", "secret", now - chrono::Duration::days(2)).unwrap();
+        a.put_install("idle", "secret", now - chrono::Duration::days(2)).unwrap();
+        assert_eq!(b.install_secret("install").unwrap().as_deref(), Some("secret"));
+        assert_eq!(b.install_secret("stranger").unwrap(), None);
+
+        // used through one instance, so another doesn't forget it as idle
+        a.install_used("install", now).unwrap();
+        assert_eq!(b.remove_idle_installs(now - chrono::Duration::days(1), &HashSet::new()).unwrap(), 1);
+        assert_eq!(a.install_secret("idle").unwrap(), None);
+        assert!(a.install_secret("install").unwrap().is_some());
+
+        // a signature accepted by one instance is a replay on the other until it expires
+        let now = now.timestamp();
+        assert!(a.first_signature_use(b"signature", now + 300, now).unwrap());
+        assert!(!b.first_signature_use(b"signature", now + 300, now).unwrap());
+        assert!(b.first_signature_use(b"signature", now + 600, now + 301).unwrap());
+
+        let webhook: StoredWebhook = serde_json::from_value(serde_json::json!({
+            "id": "hook",
+            "url": "https://hooks.example.com/",
+            "division": { "competition_id": 1, "division_id": 1 },
+            "teams": null,
+            "secret": "secret",
+            "enabled": true,
+        }))
+        .unwrap();
+        a.put_webhook(&webhook).unwrap();
+        a.put_webhook(&webhook).unwrap();
+        assert_eq!(b.webhooks().unwrap().len(), 1);
+        b.remove_webhook("hook").unwrap();
+        assert!(a.webhooks().unwrap().is_empty());
+
+        let division = CompetitionDivisionPair::new(1, 1);
+        a.modify_subscriptions(&mut |subscriptions| {
+            subscriptions.insert(division.clone(), vec![crate::tests::token_pair("5839A", crate::tests::LIVE_ACTIVITY_TOKEN)]);
+        }).unwrap();
+        assert_eq!(b.subscriptions().unwrap()[&division][0].team_name, "5839A");
+        b.modify_subscriptions(&mut |subscriptions| subscriptions.clear()).unwrap();
+        assert!(a.subscriptions().unwrap().is_empty());
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::time::SystemTime;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -16,10 +17,11 @@ pub struct CompetitionAttributesContentState {
 }
 
 impl CompetitionAttributesContentState {
-    pub fn from_matchlist(unsorted_matches: &[Match], team_name: &str) -> Self {
+    /// `corrected` holds the IDs of matches whose scores have been corrected, which are flagged.
+    pub fn from_matchlist(unsorted_matches: &[Match], team_name: &str, corrected: &HashSet<i32>) -> Self {
         let team_name = team_name.to_uppercase();
 
-        let mut matches= unsorted_matches.clone().to_vec();
+        let mut matches= unsorted_matches.to_vec();
 
         matches.sort_by(|x, x1| {
             let mut round_sum_x = x.round as f32;
@@ -34,7 +36,7 @@ impl CompetitionAttributesContentState {
 
         let mut last_scored_index = 0;
         let mut team_next_match = 0;
-        let mut matches_skipped = true;
+        let mut matches_skipped;
 
         for (index, m) in matches.iter().enumerate() {
             // Check if match has a score
@@ -61,13 +63,13 @@ impl CompetitionAttributesContentState {
 
         // Get matches using safe indexing
         let last_match = matches.get(last_scored_index)
-            .map(|m| DisplayMatch::from(m));
+            .map(|m| DisplayMatch::flagged(m, corrected));
 
         let next_match = matches.get(last_scored_index + 1)
-            .map(|m| DisplayMatch::from(m));
+            .map(|m| DisplayMatch::flagged(m, corrected));
 
         let team_next_match = matches.get(team_next_match)
-            .map(|m| DisplayMatch::from(m));
+            .map(|m| DisplayMatch::flagged(m, corrected));
 
         CompetitionAttributesContentState {
             last_match,
@@ -77,6 +79,10 @@ impl CompetitionAttributesContentState {
     }
 }
 
+pub fn has_score(m: &Match) -> bool {
+    m.alliances.iter().any(|a| a.score != 0)
+}
+
 impl From<&Match> for DisplayMatch {
     fn from(m: &Match) -> Self {
         // Parse date strings into DateTime<Utc>
@@ -111,7 +117,7 @@ impl From<&Match> for DisplayMatch {
             red_alliance: Alliance {
                 team1: red_alliance
                     .and_then(|a| a.teams.first())
-                    .map_or_else(|| String::new(), |t| t.team.name.to_string()),
+                    .map_or_else(String::new, |t| t.team.name.to_string()),
                 team2: red_alliance
                     .and_then(|a| a.teams.get(1))
                     .map(|t| t.team.name.to_string()),
@@ -120,12 +126,13 @@ impl From<&Match> for DisplayMatch {
             blue_alliance: Alliance {
                 team1: blue_alliance
                     .and_then(|a| a.teams.first())
-                    .map_or_else(|| String::new(), |t| t.team.name.to_string()),
+                    .map_or_else(String::new, |t| t.team.name.to_string()),
                 team2: blue_alliance
                     .and_then(|a| a.teams.get(1))
                     .map(|t| t.team.name.to_string()),
                 score: blue_score_new,
             },
+            corrected: false,
         }
     }
 }
@@ -154,6 +161,9 @@ pub struct DisplayMatch {
     pub start_time: Option<SystemTime>,
     pub red_alliance: Alliance,
     pub blue_alliance: Alliance,
+    /// a referee has changed its score since it was first posted
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub corrected: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -163,3 +173,76 @@ pub struct Alliance {
     pub team2: Option<String>,
     pub score: Option<i32>
 }
+
+impl DisplayMatch {
+    /// `m` as displayed, flagged if its ID is in `corrected`.
+    pub fn flagged(m: &Match, corrected: &HashSet<i32>) -> Self {
+        DisplayMatch {
+            corrected: corrected.contains(&m.id),
+            ..DisplayMatch::from(m)
+        }
+    }
+
+    pub fn involves_team(&self, team_name: &str) -> bool {
+        let team_name = team_name.to_uppercase();
+        self.red_alliance.has_team(&team_name) || self.blue_alliance.has_team(&team_name)
+    }
+
+    /// Title and body for a banner announcing this match's result from `team_name`'s point of view.
+    pub fn result_alert(&self, team_name: &str) -> (String, String) {
+        let team_name = team_name.to_uppercase();
+        let red_score = self.red_alliance.score.unwrap_or(0);
+        let blue_score = self.blue_alliance.score.unwrap_or(0);
+
+        let (team_score, opponent_score) = if self.blue_alliance.has_team(&team_name) {
+            (blue_score, red_score)
+        } else {
+            (red_score, blue_score)
+        };
+
+        let outcome = match team_score.cmp(&opponent_score) {
+            std::cmp::Ordering::Greater => "won",
+            std::cmp::Ordering::Less => "lost",
+            std::cmp::Ordering::Equal => "tied",
+        };
+
+        let title = format!("{}: {} {}", self.name, team_name, outcome);
+        let body = format!(
+            "{} {} - {} {}",
+            self.red_alliance.team_list(),
+            red_score,
+            blue_score,
+            self.blue_alliance.team_list()
+        );
+
+        (title, body)
+    }
+
+    /// Title and body for a banner announcing that this match's score was changed from `previous`'s.
+    pub fn correction_alert(&self, previous: &DisplayMatch, team_name: &str) -> (String, String) {
+        let (_, body) = self.result_alert(team_name);
+        let title = format!("{}: score corrected", self.name);
+        let body = format!(
+            "{} (was {} - {})",
+            body,
+            previous.red_alliance.score.unwrap_or(0),
+            previous.blue_alliance.score.unwrap_or(0)
+        );
+
+        (title, body)
+    }
+}
+
+impl Alliance {
+    fn has_team(&self, team_name: &str) -> bool {
+        self.team1.to_uppercase() == team_name
+            || self.team2.as_ref().is_some_and(|t| t.to_uppercase() == team_name)
+    }
+
+    fn team_list(&self) -> String {
+        match &self.team2 {
+            Some(team2) => format!("{} & {}", self.team1, team2),
+            None => self.team1.clone(),
+        }
+    }
+}
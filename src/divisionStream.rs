@@ -0,0 +1,366 @@
+use futures_util::{future, stream, SinkExt, Stream, StreamExt};
+use robotevents::schema::Match;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use tokio::sync::broadcast;
+use warp::sse::Event;
+use warp::ws::{Message, WebSocket};
+use crate::competitionAttributes::{CompetitionAttributesContentState, DisplayMatch};
+use crate::matchDiff::MatchChange;
+use crate::{CompetitionDivisionPair, StateStore};
+
+/// A change to a division's match list, as broadcast by `update_all_subscriptions`.
+#[derive(Debug, Clone)]
+pub struct DivisionUpdate {
+    pub division: CompetitionDivisionPair,
+    pub matches: Vec<Match>,
+    pub changes: Vec<MatchChange>,
+}
+
+/// One message on a division stream. SSE uses `type` as the event name; WebSocket
+/// clients get the whole frame as JSON text.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamFrame {
+    Snapshot {
+        matches: Vec<DisplayMatch>,
+    },
+    ContentState {
+        team: String,
+        content_state: Box<CompetitionAttributesContentState>,
+    },
+    /// carries its own `type`
+    #[serde(untagged)]
+    Change(MatchChange),
+}
+
+impl StreamFrame {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StreamFrame::Snapshot { .. } => "snapshot",
+            StreamFrame::ContentState { .. } => "content_state",
+            StreamFrame::Change(change) => change.name(),
+        }
+    }
+
+    fn snapshot(matches: &[Match], corrected: &HashSet<i32>) -> Self {
+        StreamFrame::Snapshot {
+            matches: matches.iter().map(|m| DisplayMatch::flagged(m, corrected)).collect(),
+        }
+    }
+
+    fn content_state(matches: &[Match], team: &str, corrected: &HashSet<i32>) -> Self {
+        StreamFrame::ContentState {
+            team: team.to_string(),
+            content_state: Box::new(CompetitionAttributesContentState::from_matchlist(matches, team, corrected)),
+        }
+    }
+
+    /// The frame as JSON, or `None` if it can't be encoded, in which case it's logged and skipped.
+    fn to_json(&self) -> Option<String> {
+        serde_json::to_string(self)
+            .map_err(|e| tracing::error!(frame = self.name(), error = %e, "unable to encode stream frame; skipping it"))
+            .ok()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamQuery {
+    team: Option<String>,
+}
+
+/// Keeps a division reserved with `reserve_division` in the poll loop for as long as a
+/// stream is open.
+struct WatchGuard {
+    state_store: StateStore,
+    division: CompetitionDivisionPair,
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        self.state_store.unwatch_division(&self.division);
+    }
+}
+
+/// Check a division a stream asks for and reserve it a place in the poll loop. Anyone can open a
+/// stream, so it's held to the same checks as a subscription.
+async fn watch(state_store: &StateStore, division: CompetitionDivisionPair) -> Result<WatchGuard, warp::Rejection> {
+    state_store.validate_division(&division).await?;
+    state_store.reserve_division(&division).await?;
+
+    Ok(WatchGuard {
+        state_store: state_store.clone(),
+        division,
+    })
+}
+
+/// Snapshot on connect followed by the diffs for one division, personalized for `team` if given.
+async fn division_frames(
+    state_store: StateStore,
+    guard: WatchGuard,
+    team: Option<String>,
+) -> impl Stream<Item = StreamFrame> + Send {
+    let division = guard.division.clone();
+
+    // subscribe before taking the snapshot so nothing falls between the two
+    let receiver = state_store.updates.subscribe();
+
+    let matches = state_store.cached_matches(&division).await.unwrap_or_default();
+    let corrected = state_store.corrections.corrected_matches(&division);
+    let mut initial = vec![StreamFrame::snapshot(&matches, &corrected)];
+    if let Some(team) = &team {
+        initial.push(StreamFrame::content_state(&matches, team, &corrected));
+    }
+
+    let updates = stream::unfold((receiver, guard, team), |(mut receiver, guard, team)| async move {
+        loop {
+            let frames = match receiver.recv().await {
+                Ok(update) if update.division == guard.division => {
+                    let mut frames: Vec<StreamFrame> = update.changes.into_iter().map(StreamFrame::Change).collect();
+                    if let Some(team) = &team {
+                        let corrected = guard.state_store.corrections.corrected_matches(&guard.division);
+                        frames.push(StreamFrame::content_state(&update.matches, team, &corrected));
+                    }
+                    frames
+                }
+                Ok(_) => continue,
+                // we missed some diffs, so resync the client with a fresh snapshot
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let matches = guard.state_store.matches.read().await
+                        .get(&guard.division)
+                        .cloned()
+                        .unwrap_or_default();
+                    let corrected = guard.state_store.corrections.corrected_matches(&guard.division);
+                    vec![StreamFrame::snapshot(&matches, &corrected)]
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+
+            return Some((stream::iter(frames), (receiver, guard, team)));
+        }
+    })
+    .flatten();
+
+    // end the stream at shutdown so the server can drain
+    let shutdown = state_store.shutdown.clone();
+    stream::iter(initial).chain(updates).take_until(async move { shutdown.requested().await })
+}
+
+pub async fn stream_division_sse(
+    competition_id: i32,
+    division_id: i32,
+    query: StreamQuery,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let guard = watch(&state_store, CompetitionDivisionPair::new(competition_id, division_id)).await?;
+
+    let events = division_frames(state_store, guard, query.team)
+        .await
+        .filter_map(|frame| future::ready(
+            frame.to_json().map(|json| Ok::<_, Infallible>(Event::default().event(frame.name()).data(json))),
+        ));
+
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+}
+
+pub async fn stream_division_ws(
+    competition_id: i32,
+    division_id: i32,
+    query: StreamQuery,
+    ws: warp::ws::Ws,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let guard = watch(&state_store, CompetitionDivisionPair::new(competition_id, division_id)).await?;
+
+    Ok(ws.on_upgrade(move |socket| forward_to_socket(socket, state_store, guard, query.team)))
+}
+
+async fn forward_to_socket(
+    socket: WebSocket,
+    state_store: StateStore,
+    guard: WatchGuard,
+    team: Option<String>,
+) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut frames = Box::pin(division_frames(state_store, guard, team).await);
+
+    loop {
+        tokio::select! {
+            frame = frames.next() => match frame {
+                Some(frame) => {
+                    let Some(text) = frame.to_json() else { continue };
+                    if sender.send(Message::text(text)).await.is_err() {
+                        break;
+                    }
+                }
+                // shutting down: say so rather than just dropping the connection
+                None => {
+                    let _ = sender.send(Message::close_with(1001u16, "server shutting down")).await;
+                    break;
+                }
+            },
+            // the stream is server -> client only; anything but a close is ignored
+            message = receiver.next() => match message {
+                Some(Ok(message)) if !message.is_close() => continue,
+                _ => break,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matchSource::{FixtureClock, FixtureSource, Recording, Snapshot};
+    use crate::mockApns::{MockApns, MockApnsConfig};
+    use crate::config::Config;
+    use crate::pushProvider::PushProviders;
+    use crate::tests::{test_match, test_store_with_source, KnownDivisions};
+    use std::sync::Arc;
+    use serde_json::Value;
+    use warp::hyper::body::HttpBody;
+    use warp::hyper::{Body, Client};
+
+    fn store(mock: &MockApns) -> StateStore {
+        test_store_with_source(mock, FixtureSource::new(vec![Recording {
+            competition_id: 1,
+            division_id: 1,
+            snapshots: vec![Snapshot {
+                offset_secs: 0,
+                matches: vec![
+                    test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (0, 0)),
+                    test_match(2, 2, ["4444E", "5555F"], ["6666G", "7777H"], (0, 0)),
+                ],
+            }],
+        }], FixtureClock::manual()))
+    }
+
+    /// Q 1 gets its score.
+    fn post_score(store: &StateStore) {
+        let scored = test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (80, 95));
+        store.updates.send(DivisionUpdate {
+            division: CompetitionDivisionPair::new(1, 1),
+            matches: vec![scored.clone(), test_match(2, 2, ["4444E", "5555F"], ["6666G", "7777H"], (0, 0))],
+            changes: vec![MatchChange::ScorePosted { display_match: DisplayMatch::from(&scored) }],
+        }).unwrap();
+    }
+
+    /// More updates than the channel holds, all for another division, before the stream reads any.
+    fn overflow(store: &StateStore) {
+        for _ in 0..65 {
+            store.updates.send(DivisionUpdate {
+                division: CompetitionDivisionPair::new(2, 2),
+                matches: Vec::new(),
+                changes: Vec::new(),
+            }).unwrap();
+        }
+    }
+
+    /// The next named SSE event and its data, skipping keep-alives, or `None` once the stream ends.
+    async fn next_event(body: &mut Body, buffered: &mut String) -> Option<(String, Value)> {
+        loop {
+            while let Some(end) = buffered.find("\n\n") {
+                let raw: String = buffered.drain(..end + 2).collect();
+                let field = |name: &str| raw.lines().find_map(|line| line.strip_prefix(name)).map(str::to_string);
+                if let (Some(event), Some(data)) = (field("event:"), field("data:")) {
+                    return Some((event, serde_json::from_str(&data).unwrap()));
+                }
+            }
+            let chunk = body.data().await?.unwrap();
+            buffered.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+
+    async fn next_frame(client: &mut warp::test::WsClient) -> Value {
+        serde_json::from_str(client.recv().await.unwrap().to_str().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn sse_sends_a_snapshot_then_changes_until_shutdown() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = store(&mock);
+        let (addr, server) = warp::serve(crate::routes(store.clone())).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let uri = format!("http://{}/v1/stream/1/1?team=5839A", addr).parse().unwrap();
+        let response = Client::new().get(uri).await.unwrap();
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let mut body = response.into_body();
+        let mut buffered = String::new();
+
+        let (event, snapshot) = next_event(&mut body, &mut buffered).await.unwrap();
+        assert_eq!(event, "snapshot");
+        assert_eq!(snapshot["matches"].as_array().unwrap().len(), 2);
+        let (event, content_state) = next_event(&mut body, &mut buffered).await.unwrap();
+        assert_eq!((event.as_str(), content_state["team"].as_str()), ("content_state", Some("5839A")));
+
+        post_score(&store);
+        let (event, change) = next_event(&mut body, &mut buffered).await.unwrap();
+        assert_eq!(event, "score_posted");
+        assert_eq!(change["match"]["redAlliance"]["score"], 80);
+        assert_eq!(next_event(&mut body, &mut buffered).await.unwrap().0, "content_state");
+
+        // having missed updates, the client is brought back in line with a fresh snapshot
+        overflow(&store);
+        let (event, snapshot) = next_event(&mut body, &mut buffered).await.unwrap();
+        assert_eq!(event, "snapshot");
+        assert_eq!(snapshot["matches"].as_array().unwrap().len(), 2);
+
+        store.shutdown.begin();
+        assert!(next_event(&mut body, &mut buffered).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn websocket_sends_a_snapshot_then_changes_until_shutdown() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = store(&mock);
+        let mut client = warp::test::ws()
+            .path("/v1/ws/1/1")
+            .handshake(crate::routes(store.clone()))
+            .await
+            .unwrap();
+
+        let snapshot = next_frame(&mut client).await;
+        assert_eq!(snapshot["type"], "snapshot");
+        assert_eq!(snapshot["matches"].as_array().unwrap().len(), 2);
+
+        post_score(&store);
+        let change = next_frame(&mut client).await;
+        assert_eq!(change["type"], "score_posted");
+        assert_eq!(change["match"]["name"], "Q 1");
+
+        overflow(&store);
+        assert_eq!(next_frame(&mut client).await["type"], "snapshot");
+
+        store.shutdown.begin();
+        client.recv_closed().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn streams_are_held_to_the_subscription_checks() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let mut config = Config::default();
+        config.limits.max_divisions = 1;
+        let store = StateStore::with_clients(config, PushProviders::new(mock.client(), None), Arc::new(KnownDivisions));
+        let routes = crate::routes(store.clone());
+        let error = |response: &warp::http::Response<warp::hyper::body::Bytes>| -> (warp::http::StatusCode, String) {
+            let body: Value = serde_json::from_slice(response.body()).unwrap();
+            (response.status(), body["code"].as_str().unwrap().to_string())
+        };
+
+        // a division RobotEvents doesn't have is never polled
+        let unknown = warp::test::request().path("/v1/stream/1/2").reply(&routes).await;
+        assert_eq!(error(&unknown), (warp::http::StatusCode::NOT_FOUND, "unknown_division".to_string()));
+        assert!(store.polled_divisions().await.is_empty());
+
+        // streams connecting together can't take the poll loop past its cap
+        let (known, other) = (CompetitionDivisionPair::new(1, 1), CompetitionDivisionPair::new(3, 1));
+        let (first, second) = tokio::join!(store.reserve_division(&known), store.reserve_division(&other));
+        assert!(first.is_ok() != second.is_ok());
+        assert_eq!(store.polled_divisions().await.len(), 1);
+
+        let full = warp::test::ws().path("/v1/ws/3/1").handshake(routes.clone()).await;
+        assert!(full.is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::http;
+use crate::{CompetitionDivisionPair, StateStore};
+
+/// Poll intervals without a finished cycle (or a successful RobotEvents fetch) before the
+/// backend stops reporting ready.
+const STALE_AFTER_POLLS: u32 = 3;
+
+/// What the poll loop has been up to, for `/readyz` and `/v1/admin/status`.
+#[derive(Debug, Clone, Default)]
+pub struct PollHealth {
+    state: Arc<Mutex<PollState>>,
+}
+
+#[derive(Debug, Default)]
+struct PollState {
+    /// when the last poll cycle finished
+    heartbeat: Option<Instant>,
+    last_poll: Option<DateTime<Utc>>,
+    last_fetch_success: Option<Instant>,
+    last_fetch_failure: Option<Instant>,
+    divisions: HashMap<CompetitionDivisionPair, DivisionHealth>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DivisionHealth {
+    /// the last successful fetch
+    pub last_polled: Option<DateTime<Utc>>,
+    pub last_error: Option<PollError>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PollError {
+    pub at: DateTime<Utc>,
+    pub message: String,
+}
+
+impl PollHealth {
+    pub fn cycle_finished(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.heartbeat = Some(Instant::now());
+        state.last_poll = Some(Utc::now());
+    }
+
+    pub fn fetch_succeeded(&self, competition_division: &CompetitionDivisionPair) {
+        let mut state = self.state.lock().unwrap();
+        state.last_fetch_success = Some(Instant::now());
+        state.divisions.entry(competition_division.clone()).or_default().last_polled = Some(Utc::now());
+    }
+
+    pub fn fetch_failed(&self, competition_division: &CompetitionDivisionPair, message: String) {
+        let mut state = self.state.lock().unwrap();
+        state.last_fetch_failure = Some(Instant::now());
+        state.divisions.entry(competition_division.clone()).or_default().last_error = Some(PollError {
+            at: Utc::now(),
+            message,
+        });
+    }
+
+    pub fn last_poll(&self) -> Option<DateTime<Utc>> {
+        self.state.lock().unwrap().last_poll
+    }
+
+    pub fn divisions(&self) -> HashMap<CompetitionDivisionPair, DivisionHealth> {
+        self.state.lock().unwrap().divisions.clone()
+    }
+
+    /// Why the poll loop isn't healthy, if it isn't.
+    fn poll_problem(&self, poll_interval: Duration) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let stale_after = poll_interval * STALE_AFTER_POLLS;
+
+        match state.heartbeat {
+            None => Some("no poll cycle has finished yet".to_string()),
+            Some(heartbeat) if heartbeat.elapsed() > stale_after => {
+                Some(format!("last poll cycle finished {}s ago", heartbeat.elapsed().as_secs()))
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// Why RobotEvents looks unreachable: fetches have been failing and none has worked lately.
+    /// A backend with nothing to poll has no reason to think it's down.
+    fn robotevents_problem(&self, poll_interval: Duration) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let stale_after = poll_interval * STALE_AFTER_POLLS;
+
+        let failure = state.last_fetch_failure?;
+        match state.last_fetch_success {
+            Some(success) if success > failure || success.elapsed() <= stale_after => None,
+            Some(success) => Some(format!("no successful fetch in {}s", success.elapsed().as_secs())),
+            None => Some("no successful fetch yet".to_string()),
+        }
+    }
+}
+
+/// `GET /healthz`: the process is up and serving.
+pub async fn healthz() -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&json!({ "status": "ok" })))
+}
+
+/// `GET /readyz`: not shutting down, APNs keys can sign, RobotEvents answered recently and the poll loop is alive.
+pub async fn readyz(state_store: StateStore) -> Result<impl warp::Reply, warp::Rejection> {
+    let poll_interval = state_store.config.poll_interval();
+
+    let apns_problem = state_store.push_providers.apns_clients()
+        .find_map(|client| client.get_token().err().map(|e| format!("{}: {}", client.bundle_id(), e)));
+
+    let shutdown_problem = state_store.shutdown.is_draining().then(|| "shutting down".to_string());
+
+    let checks = [
+        ("shutdown", shutdown_problem),
+        ("apns", apns_problem),
+        ("robotevents", state_store.poll_health.robotevents_problem(poll_interval)),
+        ("poll_loop", state_store.poll_health.poll_problem(poll_interval)),
+    ];
+    let ready = checks.iter().all(|(_, problem)| problem.is_none());
+
+    let checks: Map<String, Value> = checks.into_iter()
+        .map(|(name, problem)| (name.to_string(), json!(problem.unwrap_or_else(|| "ok".to_string()))))
+        .collect();
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({ "status": if ready { "ready" } else { "unavailable" }, "checks": checks })),
+        if ready { http::StatusCode::OK } else { http::StatusCode::SERVICE_UNAVAILABLE },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::matchSource::{FixtureClock, FixtureSource};
+    use crate::mockApns::{MockApns, MockApnsConfig};
+    use crate::pushProvider::PushProviders;
+
+    #[tokio::test]
+    async fn ready_once_a_poll_cycle_finishes() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = StateStore::with_clients(
+            Config::default(),
+            PushProviders::new(mock.client(), None),
+            Arc::new(FixtureSource::new(Vec::new(), FixtureClock::manual())),
+        );
+        let routes = crate::routes(store.clone());
+        let get = |path: &str| warp::test::request().path(path).reply(&routes);
+
+        assert_eq!(get("/healthz").await.status(), http::StatusCode::OK);
+
+        let response = get("/readyz").await;
+        assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["checks"]["apns"], "ok");
+        assert_eq!(body["checks"]["poll_loop"], "no poll cycle has finished yet");
+
+        store.update_all_subscriptions().await;
+        assert_eq!(get("/readyz").await.status(), http::StatusCode::OK);
+
+        // a division RobotEvents can't serve doesn't matter while an earlier fetch is recent
+        let division = CompetitionDivisionPair::new(1, 1);
+        store.poll_health.fetch_succeeded(&division);
+        store.poll_health.fetch_failed(&division, "503 Service Unavailable".to_string());
+        assert_eq!(get("/readyz").await.status(), http::StatusCode::OK);
+        assert_eq!(store.poll_health.divisions()[&division].last_error.as_ref().unwrap().message, "503 Service Unavailable");
+    }
+}
@@ -0,0 +1,168 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::config::Config;
+use crate::matchSource::FixtureClock;
+use crate::mockApns::{MockApns, MockApnsConfig};
+use crate::pushProvider::{Platform, TokenType};
+use crate::simulation::{mock_push_providers, parse_speed, replay, SimulatedSource};
+use crate::syntheticEvent::{generate, EventSpec};
+use crate::{DeviceSubscription, StateStore};
+
+#[derive(clap::Args, Debug)]
+pub struct LoadTestArgs {
+    #[command(flatten)]
+    spec: EventSpec,
+    /// Fake devices to subscribe, spread randomly over every team
+    #[arg(long, default_value_t = 1000)]
+    devices: usize,
+    /// Fraction of devices registered for regular notifications instead of Live Activities
+    #[arg(long, default_value_t = 0.25)]
+    notification_share: f64,
+    /// Replay speed, e.g. `600x`
+    #[arg(long, default_value = "600x", value_parser = parse_speed)]
+    speed: f64,
+    /// Seconds between polls
+    #[arg(long, default_value_t = 2)]
+    poll_interval: u64,
+    /// How long the mock APNs holds each response, to stand in for Apple's round trip
+    #[arg(long, default_value_t = 0)]
+    apns_latency_ms: u64,
+}
+
+/// `EchoScopeBackend load-test`: subscribe a crowd of fake devices to a generated event,
+/// replay it against the mock APNs server and report how the push path holds up.
+pub async fn run(args: LoadTestArgs, config_path: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    // the configured concurrency limits are what's being sized, but pushes always go to the mock
+    let config = Config::load_with(config_path.as_deref(), Config::use_mock_apns)?;
+
+    // every subscription and push would otherwise be logged between the report lines
+    let mut logging = config.logging.clone();
+    if std::env::var_os("RUST_LOG").is_none() {
+        logging.filter = "warn".to_string();
+    }
+    crate::logging::init(&logging);
+
+    let matches = generate(&args.spec);
+    let mut rng = StdRng::seed_from_u64(args.spec.seed);
+
+    // every team with a device token for it, so subscriptions land in the right division
+    let mut teams: Vec<(String, i32)> = matches.iter()
+        .flat_map(|m| m.alliances.iter()
+            .flat_map(|alliance| alliance.teams.iter())
+            .map(|alliance_team| (alliance_team.team.name.clone(), m.division.id)))
+        .collect();
+    teams.sort();
+    teams.dedup();
+    if teams.is_empty() {
+        return Err("generated event has no teams".into());
+    }
+
+    let source = Arc::new(SimulatedSource::new(matches, FixtureClock::realtime(args.speed))?);
+    let mock = MockApns::start(MockApnsConfig {
+        response_delay: Duration::from_millis(args.apns_latency_ms),
+        bundle_ids: config.bundle_ids(),
+        ..MockApnsConfig::default()
+    });
+    let store = StateStore::with_clients(config.clone(), mock_push_providers(&mock, &config), source.clone());
+
+    let subscribe_start = Instant::now();
+    let mut notification_devices = 0;
+    for _ in 0..args.devices {
+        let (team, division_id) = teams[rng.gen_range(0..teams.len())].clone();
+        let token_type = if rng.gen_bool(args.notification_share.clamp(0.0, 1.0)) {
+            notification_devices += 1;
+            TokenType::Notification
+        } else {
+            TokenType::LiveActivity
+        };
+
+        store.add_subscription_from_device(DeviceSubscription {
+            competition_id: args.spec.competition_id,
+            division_id,
+            device_token: hex::encode(rng.gen::<[u8; 32]>()),
+            watch_team: team,
+            token_type,
+            platform: Platform::Ios,
+            app_id: None,
+            ttl_secs: None,
+            install_id: None,
+        }).await?;
+    }
+    let subscribe_time = subscribe_start.elapsed();
+
+    println!(
+        "Load test: {} divisions, {} teams, {} matches, {} devices ({} notification) at {}x",
+        args.spec.divisions,
+        teams.len(),
+        source.match_count(),
+        args.devices,
+        notification_devices,
+        args.speed
+    );
+
+    let run_start = Instant::now();
+    let cycles = replay(&store, &source, Duration::from_secs(args.poll_interval)).await;
+    let run_time = run_start.elapsed();
+
+    let deliveries = mock.deliveries();
+
+    // latency is measured from the start of the poll cycle that picked up the change, so it
+    // includes queueing behind every other device in that cycle
+    let mut latencies: Vec<Duration> = deliveries.iter()
+        .filter_map(|delivery| cycles.iter()
+            .rev()
+            .find(|(started, _)| *started <= delivery.received_at)
+            .map(|(started, _)| delivery.received_at - *started))
+        .collect();
+    latencies.sort();
+
+    let busy_cycles: Vec<Duration> = cycles.iter()
+        .filter(|(started, elapsed)| deliveries.iter().any(|delivery| delivery.received_at >= *started && delivery.received_at <= *started + *elapsed))
+        .map(|(_, elapsed)| *elapsed)
+        .collect();
+    let busy_time: Duration = busy_cycles.iter().sum();
+
+    let mut cycle_times: Vec<Duration> = cycles.iter().map(|(_, elapsed)| *elapsed).collect();
+    cycle_times.sort();
+
+    println!("Subscribed {} devices in {:?}", args.devices, subscribe_time);
+    println!("Replayed in {:?}: {} poll cycles, {} with pushes", run_time, cycles.len(), busy_cycles.len());
+    println!("Pushes delivered: {}", deliveries.len());
+    println!(
+        "Push latency: p50 {:?}, p95 {:?}, p99 {:?}, max {:?}",
+        percentile(&latencies, 50.0),
+        percentile(&latencies, 95.0),
+        percentile(&latencies, 99.0),
+        latencies.last().copied().unwrap_or_default()
+    );
+    println!(
+        "Throughput: {:.0} pushes/s while pushing",
+        deliveries.len() as f64 / busy_time.as_secs_f64().max(f64::EPSILON)
+    );
+    println!(
+        "Poll cycle: p50 {:?}, p95 {:?}, max {:?}",
+        percentile(&cycle_times, 50.0),
+        percentile(&cycle_times, 95.0),
+        cycle_times.last().copied().unwrap_or_default()
+    );
+
+    if cycle_times.last().is_some_and(|longest| *longest > Duration::from_secs(args.poll_interval)) {
+        println!("WARNING: Poll cycles overran the {}s poll interval", args.poll_interval);
+    }
+
+    Ok(())
+}
+
+/// Nearest-rank percentile of an already sorted list.
+fn percentile(sorted: &[Duration], percent: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+
+    let rank = ((percent / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
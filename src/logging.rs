@@ -0,0 +1,56 @@
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing_subscriber::EnvFilter;
+use crate::config::{LogFormat, LoggingSettings};
+
+static REDACT_DEVICE_TOKENS: AtomicBool = AtomicBool::new(true);
+
+/// Install the global subscriber. Only the first call in a process takes effect.
+pub fn init(settings: &LoggingSettings) {
+    REDACT_DEVICE_TOKENS.store(settings.redact_device_tokens, Ordering::Relaxed);
+
+    // `Config::load` has already rejected filters that don't parse
+    let filter = EnvFilter::try_new(&settings.filter).unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    let _ = match settings.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).try_init(),
+    };
+}
+
+/// A device token as it should appear in logs: a short stable hash unless redaction is
+/// turned off, so one device's pushes can still be followed without logging the token.
+pub struct DeviceToken<'a>(&'a str);
+
+pub fn device_token(token: &str) -> DeviceToken<'_> {
+    DeviceToken(token)
+}
+
+impl fmt::Display for DeviceToken<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !REDACT_DEVICE_TOKENS.load(Ordering::Relaxed) {
+            return f.write_str(self.0);
+        }
+
+        let digest = Sha256::digest(self.0.as_bytes());
+        write!(f, "#{}", hex::encode(&digest[..6]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_tokens_are_hashed() {
+        let token = "0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9";
+        let logged = device_token(token).to_string();
+
+        assert_eq!(logged.len(), 13);
+        assert!(!logged.contains(&token[..8]));
+        assert_eq!(logged, device_token(token).to_string());
+        assert_ne!(logged, device_token(&token[1..]).to_string());
+    }
+}
@@ -1,21 +1,56 @@
+#![allow(non_snake_case)]
+
+mod admin;
+mod adminCli;
+mod apiError;
+mod apnsClient;
+mod clientAuth;
+mod cluster;
 mod competitionAttributes;
+mod config;
+mod divisionStream;
+mod fcmClient;
+mod health;
 mod liveActivityApns;
+mod loadTest;
+mod logging;
+mod matchApi;
+mod matchDiff;
+mod matchSource;
+mod metrics;
+mod mockApns;
+mod pushProvider;
+mod rateLimit;
+mod scoreCorrections;
+mod shutdown;
+mod simulation;
+mod storage;
+mod syntheticEvent;
+mod webhooks;
 
-use robotevents::{client, RobotEvents};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use futures_util::{stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::Duration;
-use robotevents::query::{DivisionMatchesQuery, PaginatedQuery};
 use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::join;
-use tokio::sync::RwLock;
-use tokio::time::{sleep, sleep_until};
+use tokio::sync::{broadcast, RwLock};
+use tokio::time::sleep_until;
+use tracing::Instrument;
 use warp::{http, Filter};
+use crate::apiError::ApiError;
 use crate::competitionAttributes::CompetitionAttributesContentState;
-
-// add a constant for the bundle id
-const BUNDLE_ID: &str = "net.dickhans.EchoPulse";
+use crate::config::{ApnsEnvironment, Config};
+use crate::divisionStream::DivisionUpdate;
+use crate::matchDiff::{diff_matches, MatchChange};
+use crate::matchSource::{DivisionCheck, MatchSource};
+use crate::metrics::METRICS;
+use crate::pushProvider::{Platform, PushAlert, PushProviders, PushUpdate, TokenType};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct DeviceSubscription {
@@ -23,6 +58,19 @@ struct DeviceSubscription {
     division_id: i32,
     device_token: String,
     watch_team: String,
+    #[serde(default)]
+    token_type: TokenType,
+    #[serde(default)]
+    platform: Platform,
+    /// bundle ID of the app registering; the default app if absent
+    #[serde(default)]
+    app_id: Option<String>,
+    /// how long to keep the subscription; the configured default for its token type if absent
+    #[serde(default)]
+    ttl_secs: Option<u64>,
+    /// the install that signed the request, filled in by the route rather than the body
+    #[serde(skip)]
+    install_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -41,6 +89,71 @@ struct CompetitionDivisionPair {
 struct TeamTokenPair {
     team_name: String,
     device_token: String,
+    token_type: TokenType,
+    platform: Platform,
+    app_id: String,
+    /// only this install may change the subscription; `None` for unsigned registrations
+    #[serde(default)]
+    install_id: Option<String>,
+    created_at: DateTime<Utc>,
+    #[serde(default)]
+    last_pushed_at: Option<DateTime<Utc>>,
+    ttl_secs: u64,
+}
+
+impl TeamTokenPair {
+    fn owned_by(&self, install_id: Option<&str>) -> bool {
+        self.install_id.is_none() || self.install_id.as_deref() == install_id
+    }
+
+    /// A Live Activity is gone `ttl_secs` after it started whatever happens, so its subscription
+    /// is too; a notification subscription lasts while its division keeps producing pushes.
+    fn expires_at(&self) -> DateTime<Utc> {
+        let from = match self.token_type {
+            TokenType::LiveActivity => self.created_at,
+            TokenType::Notification => self.last_pushed_at.map_or(self.created_at, |pushed| pushed.max(self.created_at)),
+        };
+        from + chrono::Duration::seconds(self.ttl_secs.min(i64::MAX as u64) as i64)
+    }
+}
+
+/// One device's subscription, as the subscribe and change routes report it.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+struct SubscriptionRecord {
+    competition_id: i32,
+    division_id: i32,
+    device_token: String,
+    watch_team: String,
+    token_type: TokenType,
+    platform: Platform,
+    app_id: String,
+    created_at: DateTime<Utc>,
+    last_pushed_at: Option<DateTime<Utc>>,
+    expires_at: DateTime<Utc>,
+}
+
+impl SubscriptionRecord {
+    fn new(competition_division: &CompetitionDivisionPair, pair: &TeamTokenPair) -> Self {
+        SubscriptionRecord {
+            competition_id: competition_division.competition_id,
+            division_id: competition_division.division_id,
+            device_token: pair.device_token.clone(),
+            watch_team: pair.team_name.clone(),
+            token_type: pair.token_type,
+            platform: pair.platform,
+            app_id: pair.app_id.clone(),
+            created_at: pair.created_at,
+            last_pushed_at: pair.last_pushed_at,
+            expires_at: pair.expires_at(),
+        }
+    }
+}
+
+/// What subscribing did: a new subscription, or an update to the token's existing one.
+#[derive(Debug)]
+enum Subscribed {
+    Created(SubscriptionRecord),
+    Updated(SubscriptionRecord),
 }
 
 impl CompetitionDivisionPair {
@@ -59,113 +172,595 @@ impl CompetitionDivisionPair {
     }
 }
 
+/// An APNs client for every configured app (or the in-process mock) plus FCM if a service
+/// account is configured.
+fn push_providers_from_config(config: &Config) -> Result<PushProviders, Box<dyn Error>> {
+    let mut mock = None;
+    let mut clients = Vec::new();
+
+    for (bundle_id, apns) in config.apps() {
+        let client = match apns.environment {
+            // no Apple credentials needed; every push lands in the in-process mock
+            ApnsEnvironment::Mock => mock
+                .get_or_insert_with(|| mockApns::MockApns::start(mockApns::MockApnsConfig {
+                    bundle_ids: config.bundle_ids(),
+                    ..mockApns::MockApnsConfig::default()
+                }))
+                .client_for(bundle_id),
+            // apps sharing the default credentials share its client and provider token
+            _ if std::ptr::eq(apns, &config.apns) && !clients.is_empty() => {
+                let default: &apnsClient::ApnsClient = &clients[0];
+                default.clone().with_bundle_id(bundle_id)
+            }
+            environment => {
+                // `Config::load` has already checked these are present
+                let team_id = apns.team_id.as_deref().unwrap_or_default();
+                let key_id = apns.key_id.as_deref().unwrap_or_default();
+                let private_key = apns.private_key()?;
+
+                tracing::info!(bundle_id, team_id, key_id, "creating APNs client");
+
+                let client = apnsClient::ApnsClient::from_key(team_id, key_id, &private_key, bundle_id)
+                    .map_err(|e| format!("Unable to load APNs key {}: {}", key_id, e))?;
+                let base_url = apns.base_url.as_deref().unwrap_or(match environment {
+                    ApnsEnvironment::Production => apnsClient::APNS_PRODUCTION_URL,
+                    _ => apnsClient::APNS_SANDBOX_URL,
+                });
+                client.with_base_url(base_url)
+            }
+        };
+
+        clients.push(client.with_token_lifetime(std::time::Duration::from_secs(apns.token_lifetime_secs)));
+    }
+
+    // Android delivery is optional; without a service account those subscriptions are skipped
+    let fcm_client = match &config.fcm {
+        Some(fcm) => {
+            tracing::info!(service_account = %fcm.service_account_path.display(), "creating FCM client");
+            let fcm_client = fcmClient::FcmClient::new(&fcm.service_account_path)
+                .map_err(|e| format!("Unable to read FCM service account {}: {}", fcm.service_account_path.display(), e))?;
+            Some(match &fcm.base_url {
+                Some(base_url) => fcm_client.with_base_url(base_url),
+                None => fcm_client,
+            })
+        }
+        None => None,
+    };
+
+    let mut clients = clients.into_iter();
+    let default = clients.next().ok_or("No apps configured")?;
+
+    Ok(clients.fold(PushProviders::new(default, fcm_client), PushProviders::with_app))
+}
+
+/// Recorded fixtures if configured, otherwise RobotEvents (optionally recording as it goes).
+fn match_source_from_config(config: &Config) -> Result<Arc<dyn MatchSource>, Box<dyn Error>> {
+    // replaying recorded fixtures needs no RobotEvents token
+    if let Some(dir) = &config.robotevents.fixtures_dir {
+        return Ok(Arc::new(matchSource::FixtureSource::load_dir(dir, matchSource::FixtureClock::realtime(1.0))?));
+    }
+
+    let robot_events = matchSource::RobotEventsSource::new(config.robotevents_token()?);
+
+    Ok(match &config.robotevents.record_dir {
+        Some(dir) => Arc::new(
+            matchSource::RecordingSource::new(robot_events, dir, matchSource::FixtureClock::realtime(1.0))
+                .map_err(|e| format!("Unable to create match recording directory {}: {}", dir.display(), e))?,
+        ),
+        None => Arc::new(robot_events),
+    })
+}
+
 #[derive(Debug, Clone)]
 struct StateStore {
     subscriptions: Arc<RwLock<HashMap<CompetitionDivisionPair, Vec<TeamTokenPair>>>>,
     matches: Arc<RwLock<HashMap<CompetitionDivisionPair, Vec<robotevents::schema::Match>>>>,
-    apns_client: Arc<RwLock<liveActivityApns::LiveActivityClient>>,
-    robot_events_client: Arc<RwLock<RobotEvents>>,
+    /// when each entry in `matches` was last fetched from RobotEvents
+    fetched_at: Arc<RwLock<HashMap<CompetitionDivisionPair, Instant>>>,
+    push_providers: Arc<PushProviders>,
+    match_source: Arc<dyn MatchSource>,
+    /// change feed for stream clients, fed by `update_all_subscriptions`
+    updates: broadcast::Sender<DivisionUpdate>,
+    /// divisions kept in the poll loop by open streams, with how many streams are watching each
+    watched: Arc<Mutex<HashMap<CompetitionDivisionPair, usize>>>,
+    /// webhooks registered with any instance, whose divisions are polled too
+    webhooks: webhooks::WebhookRegistry,
+    installs: clientAuth::InstallRegistry,
+    /// scores changed after posting, for flagging matches and `/corrections`
+    corrections: scoreCorrections::CorrectionLog,
+    poll_health: health::PollHealth,
+    /// mutating requests per client IP
+    ip_limiter: rateLimit::RateLimiter,
+    /// subscribe/change requests per device token
+    token_limiter: rateLimit::RateLimiter,
+    /// install registrations per client IP
+    install_limiter: rateLimit::RateLimiter,
+    shutdown: shutdown::Shutdown,
+    /// other instances sharing the subscriptions, if any, and which divisions are ours
+    cluster: cluster::Cluster,
+    config: Arc<Config>,
 }
 
 impl StateStore {
-    fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let team_id = std::env::var("APPLE_TEAM_ID").expect("APPLE_TEAM_ID not set");
-        let key_id = std::env::var("APPLE_KEY_ID").expect("APPLE_KEY_ID not set");
-        let key_path = std::env::var("APPLE_KEY_PATH").expect("APPLE_KEY_PATH not set");
-
-        println!("Creating APNS client with team_id {}, key_id {}, key_path {}", team_id, key_id, key_path);
-
-        let mut apns_client =
-            liveActivityApns::LiveActivityClient::new(&team_id, &key_id, &key_path, BUNDLE_ID).expect("Unable to create APNS client");
+    fn new(config: Config) -> Result<Self, Box<dyn Error>> {
+        let push_providers = push_providers_from_config(&config)?;
+        let match_source = match_source_from_config(&config)?;
+        let cluster = cluster::Cluster::from_config(&config.cluster)?;
 
         Ok(Self {
+            webhooks: webhooks::WebhookRegistry::new().with_cluster(cluster.clone()),
+            cluster,
+            ..Self::with_clients(config, push_providers, match_source)
+        })
+    }
+
+    fn with_clients(config: Config, push_providers: PushProviders, match_source: Arc<dyn MatchSource>) -> Self {
+        Self {
             subscriptions: Arc::new(RwLock::new(HashMap::new())),
             matches: Arc::new(RwLock::new(HashMap::new())),
-            apns_client: Arc::new(RwLock::new(apns_client)),
-            robot_events_client: Arc::new(RwLock::new(client::RobotEvents::new(
-                std::env::var("ROBOTEVENTS_TOKEN").expect("ROBOTEVENTS_TOKEN not set"),
-            ))),
-        })
+            fetched_at: Arc::new(RwLock::new(HashMap::new())),
+            push_providers: Arc::new(push_providers),
+            match_source,
+            updates: broadcast::channel(64).0,
+            watched: Arc::new(Mutex::new(HashMap::new())),
+            webhooks: webhooks::WebhookRegistry::new(),
+            installs: clientAuth::InstallRegistry::default(),
+            corrections: scoreCorrections::CorrectionLog::default(),
+            poll_health: health::PollHealth::default(),
+            ip_limiter: rateLimit::RateLimiter::new(config.limits.requests_per_minute_per_ip),
+            token_limiter: rateLimit::RateLimiter::new(config.limits.requests_per_minute_per_token),
+            install_limiter: rateLimit::RateLimiter::per_hour(config.limits.installs_per_hour_per_ip),
+            shutdown: shutdown::Shutdown::default(),
+            cluster: cluster::Cluster::default(),
+            config: Arc::new(config),
+        }
+    }
+
+    fn watch_division(&self, competition_division: &CompetitionDivisionPair) {
+        *self.watched.lock().unwrap().entry(competition_division.clone()).or_insert(0) += 1;
+    }
+
+    /// Every division the poll loop covers: those with subscribers plus those only streams or webhooks watch.
+    async fn polled_divisions(&self) -> Vec<CompetitionDivisionPair> {
+        let mut divisions = self.claimable_divisions().await;
+        divisions.extend(
+            self.watched.lock().unwrap().keys()
+                .filter(|competition_division| !divisions.contains(competition_division))
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+        divisions
     }
 
-    async fn test_push_notifs(&self) {
-        // use test method in liveActivityApns
-        let mut apns_client = self.apns_client.write().await;
+    /// The divisions some instance has to poll and push for: those with subscribers or an
+    /// enabled webhook.
+    async fn claimable_divisions(&self) -> Vec<CompetitionDivisionPair> {
+        let mut divisions: Vec<CompetitionDivisionPair> = self.subscriptions.read().await.keys().cloned().collect();
+        for competition_division in self.webhooks.divisions().await {
+            if !divisions.contains(&competition_division) {
+                divisions.push(competition_division);
+            }
+        }
+        divisions
+    }
 
-        // go through each device and update it with the liveActivityApns::test_live_activity() function
-        // for each device in the subscriptions hashmap
+    /// Whether `competition_division` can be polled without going past `limits.max_divisions`.
+    /// Divisions already in the poll loop always can. This is only an early answer, before a
+    /// division is checked with RobotEvents; whatever adds it repeats the check with
+    /// `admit_division`.
+    async fn can_poll(&self, competition_division: &CompetitionDivisionPair) -> Result<(), rateLimit::AtCapacity> {
+        let divisions = self.polled_divisions().await;
+        if divisions.contains(competition_division) || divisions.len() < self.config.limits.max_divisions {
+            Ok(())
+        } else {
+            tracing::warn!(?competition_division, max_divisions = self.config.limits.max_divisions, "turning away a new division");
+            Err(rateLimit::AtCapacity)
+        }
+    }
+
+    /// Keep `competition_division` in the poll loop for a stream, unless that would take the loop
+    /// past `limits.max_divisions`. The check and the reservation are made under one lock, so
+    /// streams connecting at once can't together go past the cap. Release it with `unwatch_division`.
+    async fn reserve_division(&self, competition_division: &CompetitionDivisionPair) -> Result<(), rateLimit::AtCapacity> {
         let subscriptions = self.subscriptions.read().await;
+        let webhooks = self.webhooks.divisions().await;
+
+        let mut watched = self.watched.lock().unwrap();
+        Self::admit_division(competition_division, self.config.limits.max_divisions, subscriptions.keys(), &webhooks, &watched)?;
+        *watched.entry(competition_division.clone()).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Turn away `competition_division` if it isn't polled yet and the poll loop already covers
+    /// `max_divisions`. Subscriptions, webhooks and streams each add divisions under their own
+    /// lock; callers read `subscribed`, `webhooks` and `watched` under those locks, always in
+    /// that order, and hold them until the division is added, so requests racing for the last
+    /// place can't all get it.
+    fn admit_division<'a>(
+        competition_division: &CompetitionDivisionPair,
+        max_divisions: usize,
+        subscribed: impl Iterator<Item = &'a CompetitionDivisionPair>,
+        webhooks: &[CompetitionDivisionPair],
+        watched: &HashMap<CompetitionDivisionPair, usize>,
+    ) -> Result<(), rateLimit::AtCapacity> {
+        let mut polled: HashSet<&CompetitionDivisionPair> = subscribed.collect();
+        polled.extend(webhooks);
+        polled.extend(watched.keys());
+
+        if polled.contains(competition_division) || polled.len() < max_divisions {
+            Ok(())
+        } else {
+            tracing::warn!(?competition_division, max_divisions, "turning away a new division");
+            Err(rateLimit::AtCapacity)
+        }
+    }
+
+    /// Turn away divisions RobotEvents doesn't know, or from a past season, before they're
+    /// polled forever. Divisions already polled passed this when they were added, and a
+    /// RobotEvents outage lets subscriptions through rather than failing them.
+    async fn validate_division(&self, competition_division: &CompetitionDivisionPair) -> Result<(), ApiError> {
+        if self.polled_divisions().await.contains(competition_division) {
+            return Ok(());
+        }
+
+        let check = match self.match_source.check_division(competition_division).await {
+            Ok(check) => check,
+            Err(e) => {
+                tracing::warn!(?competition_division, error = %e, "unable to check division; accepting it");
+                return Ok(());
+            }
+        };
+
+        let CompetitionDivisionPair { competition_id, division_id } = competition_division;
+        match check {
+            DivisionCheck::Valid => Ok(()),
+            DivisionCheck::UnknownEvent => Err(ApiError::not_found(
+                "unknown_event",
+                format!("RobotEvents has no event {}", competition_id),
+            )),
+            DivisionCheck::UnknownDivision => Err(ApiError::not_found(
+                "unknown_division",
+                format!("event {} has no division {}", competition_id, division_id),
+            )),
+            DivisionCheck::PastSeason { season } => Err(ApiError::bad_request(
+                "past_season",
+                format!("event {} is from {}, which is no longer the current season", competition_id, season),
+            )),
+        }
+    }
+
+    fn unwatch_division(&self, competition_division: &CompetitionDivisionPair) {
+        let mut watched = self.watched.lock().unwrap();
+        if let Some(count) = watched.get_mut(competition_division) {
+            *count -= 1;
+            if *count == 0 {
+                watched.remove(competition_division);
+            }
+        }
+    }
+
+    /// The cached match list for a division, fetching it from the match source if we don't have it
+    /// yet or if nothing has refreshed it within a poll interval. A stale copy is returned if
+    /// the refresh fails.
+    async fn cached_matches(&self, competition_division: &CompetitionDivisionPair) -> Option<Vec<robotevents::schema::Match>> {
+        {
+            let matches = self.matches.read().await;
+            let fresh = self.fetched_at.read().await.get(competition_division)
+                .is_some_and(|fetched| fetched.elapsed() < self.config.poll_interval());
+
+            if fresh {
+                if let Some(cached) = matches.get(competition_division) {
+                    return Some(cached.clone());
+                }
+            }
+        }
+
+        // no lock is held while the source answers, so the poll loop and other readers carry on
+        let requested = Instant::now();
+        let result = self.match_source.division_matches(competition_division).await;
+
+        let mut matches = self.matches.write().await;
+        let mut fetched_at = self.fetched_at.write().await;
+        match result {
+            Ok(fetched) => {
+                self.poll_health.fetch_succeeded(competition_division);
+
+                // the poll loop may have stored a newer list while this fetch was in flight
+                if fetched_at.get(competition_division).is_some_and(|fetched| *fetched > requested) {
+                    if let Some(newer) = matches.get(competition_division) {
+                        return Some(newer.clone());
+                    }
+                }
+
+                matches.insert(competition_division.clone(), fetched.clone());
+                fetched_at.insert(competition_division.clone(), Instant::now());
+                Some(fetched)
+            }
+            Err(e) => {
+                tracing::error!(?competition_division, error = %e, "unable to fetch matches");
+                self.poll_health.fetch_failed(competition_division, e.to_string());
+                matches.get(competition_division).cloned()
+            }
+        }
+    }
+
+    /// Push subscribers (all of them, or just `device_token`'s subscriptions) the content state
+    /// for their division as it stands in the cache, whether or not anything changed.
+    async fn test_push_notifs(&self, device_token: Option<&str>) -> Vec<(SubscriptionRecord, Result<(), String>)> {
+        let matches = self.matches.read().await;
+        let subscriptions = self.subscriptions.read().await;
+        let mut results = Vec::new();
 
         for (competition_division, devices) in subscriptions.iter() {
-            for TeamTokenPair {
-                team_name,
-                device_token,
-            } in devices.iter()
-            {
-                liveActivityApns::test_live_activity(&mut apns_client, device_token)
-                    .await
-                    .expect("unable to send messages");
+            let division_matches = matches.get(competition_division).map(Vec::as_slice).unwrap_or_default();
+            let corrected = self.corrections.corrected_matches(competition_division);
+
+            for pair in devices.iter().filter(|pair| device_token.is_none_or(|token| pair.device_token == token)) {
+                let update = PushUpdate {
+                    content_state: CompetitionAttributesContentState::from_matchlist(division_matches, &pair.team_name, &corrected),
+                    alerts: Vec::new(),
+                };
+
+                let result = match self.push_providers.provider_for(&pair.app_id, pair.platform, pair.token_type) {
+                    Some(provider) => provider.push_update(&pair.device_token, &update).await.map_err(|e| e.to_string()),
+                    None => Err(format!("no push provider configured for {:?}", pair.platform)),
+                };
+                results.push((SubscriptionRecord::new(competition_division, pair), result));
             }
         }
+
+        results
     }
 
-    async fn add_subscription_from_device(&self, device: DeviceSubscription) {
-        println!(
-            "Adding subscription for competition {:?} and device {}",
-            CompetitionDivisionPair::from_device(&device),
-            device.device_token
-        );
+    /// The content state each of `device_token`'s subscriptions would be pushed right now.
+    async fn content_states(&self, device_token: &str) -> Vec<(SubscriptionRecord, CompetitionAttributesContentState)> {
+        let matches = self.matches.read().await;
+        let subscriptions = self.subscriptions.read().await;
+
+        subscriptions.iter()
+            .flat_map(|(competition_division, devices)| devices.iter()
+                .filter(|pair| pair.device_token == device_token)
+                .map(|pair| {
+                    let division_matches = matches.get(competition_division).map(Vec::as_slice).unwrap_or_default();
+                    (
+                        SubscriptionRecord::new(competition_division, pair),
+                        CompetitionAttributesContentState::from_matchlist(
+                            division_matches,
+                            &pair.team_name,
+                            &self.corrections.corrected_matches(competition_division),
+                        ),
+                    )
+                }))
+            .collect()
+    }
+
+    /// Every subscription, or those for one event or division, by division then token.
+    async fn subscription_records(&self, competition_id: Option<i32>, division_id: Option<i32>) -> Vec<SubscriptionRecord> {
+        let mut records: Vec<SubscriptionRecord> = self.subscriptions.read().await.iter()
+            .filter(|(competition_division, _)| {
+                competition_id.is_none_or(|id| competition_division.competition_id == id)
+                    && division_id.is_none_or(|id| competition_division.division_id == id)
+            })
+            .flat_map(|(competition_division, devices)| devices.iter().map(|pair| SubscriptionRecord::new(competition_division, pair)))
+            .collect();
+
+        records.sort_by(|a, b| (a.competition_id, a.division_id, &a.device_token).cmp(&(b.competition_id, b.division_id, &b.device_token)));
+        records
+    }
+
+    /// Drop every subscription for `device_token`, whoever registered it.
+    async fn remove_device_token(&self, device_token: &str) -> Result<Vec<SubscriptionRecord>, ApiError> {
         let mut subscriptions = self.subscriptions.write().await;
-        let entry = subscriptions
-            .entry(CompetitionDivisionPair::from_device(&device))
-            .or_insert(Vec::new());
-        entry.push(TeamTokenPair {
-            team_name: device.watch_team.clone(),
-            device_token: device.device_token,
+        let removing = device_token.to_string();
+        let removed = self.cluster.modify_subscriptions(&mut subscriptions, move |subscriptions| {
+            let mut removed = Vec::new();
+            for (competition_division, devices) in subscriptions.iter_mut() {
+                devices.retain(|pair| {
+                    let keep = pair.device_token != removing;
+                    if !keep {
+                        removed.push(SubscriptionRecord::new(competition_division, pair));
+                    }
+                    keep
+                });
+            }
+            Self::remove_empty_subscriptions(subscriptions);
+            removed
+        })
+        .await
+        .map_err(cluster::unavailable)?;
+
+        tracing::info!(device_token = %logging::device_token(device_token), removed = removed.len(), "removed device token");
+        Ok(removed)
+    }
+
+    /// Subscribe a device, or update its subscription if the token is already subscribed to
+    /// this division, so retries don't duplicate pushes. Fails if the subscription names an
+    /// app this backend has no credentials for, or the token belongs to another install.
+    async fn add_subscription_from_device(&self, device: DeviceSubscription) -> Result<Subscribed, ApiError> {
+        let app_id = self.push_providers.resolve_app(device.app_id.as_deref())
+            .ok_or_else(|| ApiError::bad_request(
+                "unknown_app",
+                format!("Unknown app_id {}", device.app_id.as_deref().unwrap_or_default()),
+            ))?
+            .to_string();
+
+        let settings = &self.config.subscriptions;
+        let ttl_secs = device.ttl_secs.unwrap_or(match device.token_type {
+            TokenType::LiveActivity => settings.live_activity_ttl_secs,
+            TokenType::Notification => settings.notification_ttl_secs,
         });
+        if !(1..=settings.max_ttl_secs).contains(&ttl_secs) {
+            return Err(ApiError::bad_request(
+                "invalid_ttl",
+                format!("ttl_secs must be between 1 and {}", settings.max_ttl_secs),
+            ));
+        }
+
+        let competition_division = CompetitionDivisionPair::from_device(&device);
+        let mut pair = TeamTokenPair {
+            team_name: device.watch_team,
+            device_token: device.device_token,
+            token_type: device.token_type,
+            platform: device.platform,
+            app_id,
+            install_id: device.install_id,
+            created_at: Utc::now(),
+            last_pushed_at: None,
+            ttl_secs,
+        };
+
+        let mut subscriptions = self.subscriptions.write().await;
+        let (webhooks, watched, max_divisions) = (self.webhooks.divisions().await, self.watched.clone(), self.config.limits.max_divisions);
+        self.cluster.modify_subscriptions(&mut subscriptions, move |subscriptions| {
+            Self::admit_division(&competition_division, max_divisions, subscriptions.keys(), &webhooks, &watched.lock().unwrap())?;
+            let devices = subscriptions.entry(competition_division.clone()).or_default();
+
+            match devices.iter_mut().find(|existing| existing.device_token == pair.device_token) {
+                Some(existing) => {
+                    if !existing.owned_by(pair.install_id.as_deref()) {
+                        return Err(ApiError::new(
+                            http::StatusCode::CONFLICT,
+                            "token_in_use",
+                            "device_token is registered to another install",
+                        ));
+                    }
+
+                    tracing::info!(?competition_division, device_token = %logging::device_token(&pair.device_token), "updating subscription");
+                    // an unsigned registration is claimed by the first install to sign for it, and
+                    // a retry doesn't restart the clock
+                    pair.install_id = pair.install_id.or(existing.install_id.take());
+                    pair.created_at = existing.created_at;
+                    pair.last_pushed_at = existing.last_pushed_at;
+                    *existing = pair;
+                    Ok(Subscribed::Updated(SubscriptionRecord::new(&competition_division, existing)))
+                }
+                None => {
+                    tracing::info!(?competition_division, device_token = %logging::device_token(&pair.device_token), "adding subscription");
+                    let record = SubscriptionRecord::new(&competition_division, &pair);
+                    devices.push(pair);
+                    Ok(Subscribed::Created(record))
+                }
+            }
+        })
+        .await
+        .map_err(cluster::unavailable)?
     }
 
-    async fn change_subscription_from_device(&self, device: &DeviceSubscriptionChangeRequest) {
+    /// Move every subscription for `old_device_token` to `new_device_token` in one step, or
+    /// drop them all if the new token is empty. Only the install that registered a token (or
+    /// anyone, for unsigned registrations) can change it. Returns the subscriptions as they
+    /// now stand.
+    async fn change_subscription_from_device(
+        &self,
+        change: &DeviceSubscriptionChangeRequest,
+        install_id: Option<&str>,
+    ) -> Result<Vec<SubscriptionRecord>, ApiError> {
         let mut subscriptions = self.subscriptions.write().await;
+        let (change_request, install_id) = (change.clone(), install_id.map(String::from));
+
+        let records = self.cluster.modify_subscriptions(&mut subscriptions, move |subscriptions| {
+            let change = change_request;
+            let changeable = |pair: &TeamTokenPair| pair.device_token == change.old_device_token && pair.owned_by(install_id.as_deref());
+            if !subscriptions.values().flatten().any(changeable) {
+                return Err(ApiError::not_found("unknown_device_token", "no subscriptions for old_device_token"));
+            }
+
+            let mut records = Vec::new();
+            for (competition_division, devices) in subscriptions.iter_mut() {
+                if change.new_device_token.is_empty() {
+                    devices.retain(|pair| !changeable(pair));
+                    continue;
+                }
+
+                // a retried change may find the new token already here; keep that one
+                let already_moved = devices.iter().any(|pair| pair.device_token == change.new_device_token);
+                if already_moved {
+                    devices.retain(|pair| !changeable(pair));
+                }
 
-        let mut old_competition_division = None;
-        let mut old_watch_team = None;
-
-        for (competition_division, devices) in subscriptions.iter_mut() {
-            for TeamTokenPair {
-                team_name,
-                device_token,
-            } in devices.iter_mut()
-            {
-                if device_token == &device.old_device_token {
-                    old_competition_division = Some(competition_division.clone());
-                    old_watch_team = Some(team_name.clone());
-                    devices.retain(
-                        |TeamTokenPair {
-                             team_name,
-                             device_token,
-                         }| device_token != &device.old_device_token,
-                    );
-                    break;
+                for pair in devices.iter_mut() {
+                    if changeable(pair) {
+                        pair.device_token = change.new_device_token.clone();
+                    }
+                    if pair.device_token == change.new_device_token {
+                        records.push(SubscriptionRecord::new(competition_division, pair));
+                    }
                 }
             }
-        }
+            Self::remove_empty_subscriptions(subscriptions);
 
-        if device.new_device_token.is_empty() {
-            println!("Removing device with token {}", device.old_device_token);
-            Self::remove_empty_subscriptions(&mut *self.subscriptions.write().await);
-            return;
-        }
+            Ok(records)
+        })
+        .await
+        .map_err(cluster::unavailable)??;
 
-        if let Some(old_competition_division) = old_competition_division {
-            let new_subscriptions = subscriptions
-                .entry(old_competition_division)
-                .or_insert(Vec::new());
-            new_subscriptions.push(TeamTokenPair {
-                device_token: device.new_device_token.clone(),
-                team_name: old_watch_team.unwrap(),
-            });
+        if change.new_device_token.is_empty() {
+            tracing::info!(device_token = %logging::device_token(&change.old_device_token), "removing device");
+        } else {
+            tracing::info!(
+                old_device_token = %logging::device_token(&change.old_device_token),
+                new_device_token = %logging::device_token(&change.new_device_token),
+                subscriptions = records.len(),
+                "changing device token"
+            );
         }
+
+        Ok(records)
+    }
+
+    /// Drop every subscription past its expiry, and with them any division left with nobody
+    /// to poll for. Returns how many were dropped; none if the cluster store can't be reached,
+    /// in which case the next sweep tries again.
+    async fn remove_expired_subscriptions(&self, now: DateTime<Utc>) -> usize {
+        let mut subscriptions = self.subscriptions.write().await;
+
+        let expired = self.cluster.modify_subscriptions(&mut subscriptions, move |subscriptions| {
+            let mut expired = 0;
+            for (competition_division, devices) in subscriptions.iter_mut() {
+                devices.retain(|pair| {
+                    let live = pair.expires_at() > now;
+                    if !live {
+                        tracing::info!(
+                            ?competition_division,
+                            device_token = %logging::device_token(&pair.device_token),
+                            token_type = ?pair.token_type,
+                            "subscription expired"
+                        );
+                        expired += 1;
+                    }
+                    live
+                });
+            }
+            Self::remove_empty_subscriptions(subscriptions);
+            expired
+        })
+        .await
+        .unwrap_or_default();
+        drop(subscriptions);
+
+        // nothing polls the dropped divisions any more, so their cached matches would only go stale
+        let polled = self.polled_divisions().await;
+        self.matches.write().await.retain(|competition_division, _| polled.contains(competition_division));
+        self.fetched_at.write().await.retain(|competition_division, _| polled.contains(competition_division));
+        self.corrections.retain(&polled);
+
+        METRICS.subscriptions_expired.inc_by(expired as u64);
+        expired
+    }
+
+    /// Forget installs idle for longer than `install_ttl_secs` that no longer own a
+    /// subscription or webhook. Returns how many were forgotten.
+    async fn remove_idle_installs(&self, now: DateTime<Utc>) -> usize {
+        let mut owners: HashSet<String> = self.subscriptions.read().await.values()
+            .flatten()
+            .filter_map(|pair| pair.install_id.clone())
+            .collect();
+        owners.extend(self.webhooks.install_ids().await);
+
+        let cutoff = now - chrono::Duration::seconds(self.config.subscriptions.install_ttl_secs as i64);
+        // the cluster store too, or a signed request would fetch the install back from it
+        self.cluster.remove_idle_installs(cutoff, &owners).await;
+        self.installs.remove_idle(cutoff, &owners).await
     }
 
     fn remove_empty_subscriptions(subscriptions: &mut HashMap<CompetitionDivisionPair, Vec<TeamTokenPair>>) {
@@ -173,141 +768,274 @@ impl StateStore {
     }
 
     async fn update_all_subscriptions(&self) {
+        // pick up what other instances have subscribed or registered, then settle which divisions are ours
+        self.cluster.sync_subscriptions(&mut *self.subscriptions.write().await).await;
+        self.webhooks.refresh().await;
+        let claimable = self.claimable_divisions().await;
+        let claimed = self.cluster.claim(&claimable, Utc::now()).await;
+
+        // a division taken over from another instance is diffed against what it last pushed
+        for competition_division in &claimed.acquired {
+            if let Some(shared) = self.cluster.shared_matches(competition_division).await {
+                self.matches.write().await.insert(competition_division.clone(), shared);
+            }
+        }
+
+        // other instances' divisions are still polled here if a stream wants them
+        let divisions: Vec<CompetitionDivisionPair> = self.polled_divisions().await.into_iter()
+            .filter(|competition_division| {
+                self.cluster.owns(competition_division) || self.watched.lock().unwrap().contains_key(competition_division)
+            })
+            .collect();
+        tracing::info!(divisions = divisions.len(), owned = claimed.owned.len(), "updating all subscriptions");
+
+        stream::iter(divisions.iter())
+            .for_each_concurrent(self.config.limits.poll_concurrency, |competition_division| {
+                let span = tracing::info_span!(
+                    "division",
+                    competition_id = competition_division.competition_id,
+                    division_id = competition_division.division_id
+                );
+
+                async move {
+                    let _timer = metrics::METRICS.poll_duration
+                        .with_label_values(&metrics::division_labels(competition_division))
+                        .start_timer();
+
+                    // failures are logged and recorded for /readyz; the next cycle tries again
+                    let _ = self.poll_division(competition_division).await;
+                }
+                .instrument(span)
+            })
+            .await;
+
+        self.poll_health.cycle_finished();
+    }
+
+    /// Fetch one division's matches now and push whatever changed. Returns how many matches
+    /// the division has.
+    async fn poll_division(&self, competition_division: &CompetitionDivisionPair) -> Result<usize, matchSource::MatchSourceError> {
+        match self.match_source.division_matches(competition_division).await {
+            Ok(new_matches) => {
+                self.poll_health.fetch_succeeded(competition_division);
+                let count = new_matches.len();
+                self.apply_matches(competition_division, new_matches).await;
+                Ok(count)
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "no matches found");
+                self.poll_health.fetch_failed(competition_division, e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// Take a freshly fetched match list for a division: update the cache and, if anything
+    /// changed, tell stream clients and (if this instance owns the division) push to every
+    /// subscribed device.
+    async fn apply_matches(&self, competition_division: &CompetitionDivisionPair, new_matches: Vec<robotevents::schema::Match>) {
         // mutably get the current match hash map
         let mut matches = self.matches.write().await;
 
-        // get the current subscriptions hash map
-        let subscriptions = self.subscriptions.read().await;
+        self.fetched_at.write().await.insert(competition_division.clone(), Instant::now());
 
-        // mutably get the robot events client
-        let robot_events_client = self.robot_events_client.write().await;
+        // if the matches don't match what is in the matches hash map, update the matches hash map and send a notification
+        let old_matches = matches.get(competition_division).cloned();
+        if old_matches.as_ref() == Some(&new_matches) {
+            tracing::debug!(?competition_division, "no new matches");
+            return;
+        }
 
-        // mutably get the apns client
-        let mut apns_client = self.apns_client.write().await;
+        matches.insert(competition_division.clone(), new_matches.clone());
+        drop(matches);
 
-        println!("updating all subscriptions");
+        let owned = self.cluster.owns(competition_division);
+        if owned {
+            self.cluster.share_matches(competition_division, &new_matches).await;
+        }
 
-        // for each competition division pair in the subscriptions hash map
-        for (competition_division, devices) in subscriptions.iter() {
-            // get the matches for the competition division pair
-            if let Some(new_matches) = get_matches(competition_division, &robot_events_client).await {
-                // if the matches don't match what is in the matches hash map, update the matches hash map and send a notification
-                if new_matches != *matches.get(competition_division).unwrap_or(&Vec::new()) {
-                    matches.insert(competition_division.clone(), new_matches.clone());
-
-                    // for each device in the devices vector
-                    for TeamTokenPair {
-                        team_name,
-                        device_token,
-                    } in devices.iter()
-                    {
-                        let content_state = CompetitionAttributesContentState::from_matchlist(&new_matches, team_name);
-
-                        let payload = json!({
-                        "aps": {
-                            "timestamp": chrono::Utc::now().timestamp(),
-                            "event": "update",
-                            "content-state": content_state
-                        }
-                    });
-
-                        println!("Sending notification to device {}, with payload {}", device_token, payload);
-
-                        // send a notification to the device
-                        apns_client.send_live_activity_notification(device_token, &payload).await.expect("Unable to send notification");
+        // with nothing cached (after a restart, for a division's first subscriber, or once the
+        // sweeper dropped it) the list is only a baseline: every past match would otherwise
+        // count as just added and scored, alerting and calling webhooks for the whole event
+        let changes = match &old_matches {
+            Some(old_matches) => diff_matches(old_matches, &new_matches),
+            None => Vec::new(),
+        };
+        for change in &changes {
+            tracing::info!(?competition_division, change = change.name(), r#match = change.display_match().name, "match changed");
+            METRICS.match_changes.with_label_values(&[change.name()]).inc();
+        }
+        self.corrections.record(competition_division, &changes, Utc::now());
+
+        // nobody may be streaming, in which case there's no receiver and that's fine
+        let _ = self.updates.send(DivisionUpdate {
+            division: competition_division.clone(),
+            matches: new_matches.clone(),
+            changes: changes.clone(),
+        });
+
+        // the owning instance pushes to subscribers
+        if !owned {
+            return;
+        }
+
+        let subscriptions = self.subscriptions.read().await;
+        let devices = subscriptions.get(competition_division).map(Vec::as_slice).unwrap_or_default();
+        let corrected = self.corrections.corrected_matches(competition_division);
+        let delivered = Mutex::new(Vec::new());
+
+        // send to every device through whichever channel it registered with, a few at a time
+        stream::iter(devices.iter())
+            .for_each_concurrent(self.config.limits.push_concurrency, |TeamTokenPair { team_name, device_token, token_type, platform, app_id, .. }| {
+                let update = PushUpdate {
+                    content_state: CompetitionAttributesContentState::from_matchlist(&new_matches, team_name, &corrected),
+                    // a banner for each of the team's matches that has just been scored or corrected
+                    alerts: changes.iter()
+                        .filter_map(|change| match change {
+                            MatchChange::ScorePosted { display_match } if display_match.involves_team(team_name) => {
+                                let (title, body) = display_match.result_alert(team_name);
+                                Some(PushAlert { title, body })
+                            }
+                            MatchChange::ScoreCorrected { display_match, previous, .. }
+                                if self.config.subscriptions.correction_alerts && display_match.involves_team(team_name) =>
+                            {
+                                let (title, body) = display_match.correction_alert(previous, team_name);
+                                Some(PushAlert { title, body })
+                            }
+                            _ => None,
+                        })
+                        .collect(),
+                };
+
+                let span = tracing::debug_span!(
+                    "push",
+                    device_token = %logging::device_token(device_token),
+                    ?platform,
+                    ?token_type,
+                    app_id
+                );
+
+                let delivered = &delivered;
+                async move {
+                    match self.push_providers.provider_for(app_id, *platform, *token_type) {
+                        Some(provider) => match provider.push_update(device_token, &update).await {
+                            Ok(()) => {
+                                tracing::debug!("push sent");
+                                delivered.lock().unwrap().push(device_token.clone());
+                            }
+                            Err(e) => tracing::warn!(error = %e, "unable to send notification"),
+                        },
+                        None => tracing::error!("no push provider configured"),
                     }
-                } else {
-                    println!("No new matches found for competition division pair {:?}", competition_division);
                 }
-            } else {
-                println!("ERROR: No matches found for competition division pair {:?}", competition_division);
-            }
+                .instrument(span)
+            })
+            .await;
+        drop(subscriptions);
+
+        let delivered = delivered.into_inner().unwrap();
+        if delivered.is_empty() {
+            return;
         }
+
+        let now = Utc::now();
+        let mut subscriptions = self.subscriptions.write().await;
+        let competition_division = competition_division.clone();
+        // if the store is down the pushes go unrecorded, which at worst expires a notification
+        // subscription a little early
+        let _ = self.cluster.modify_subscriptions(&mut subscriptions, move |subscriptions| {
+            if let Some(devices) = subscriptions.get_mut(&competition_division) {
+                for pair in devices.iter_mut().filter(|pair| delivered.contains(&pair.device_token)) {
+                    pair.last_pushed_at = Some(now);
+                }
+            }
+        })
+        .await;
     }
 }
 
 async fn add_device(
-    device: DeviceSubscription,
+    install_id: Option<String>,
+    mut device: DeviceSubscription,
     state_store: StateStore,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    // let r = competition.grocery_list.read();
-    // Ok(warp::reply::json(&*r))
-    state_store.add_subscription_from_device(device).await;
-    Ok(warp::reply::with_status(
-        "Added device",
-        http::StatusCode::CREATED,
-    ))
+    device.install_id = install_id;
+
+    device.platform.validate_token(&device.device_token)
+        .map_err(|e| ApiError::bad_request("invalid_device_token", e))?;
+    state_store.token_limiter.check(&device.device_token)?;
+
+    let competition_division = CompetitionDivisionPair::from_device(&device);
+    state_store.can_poll(&competition_division).await?;
+    state_store.validate_division(&competition_division).await?;
+
+    Ok(match state_store.add_subscription_from_device(device).await? {
+        Subscribed::Created(record) => warp::reply::with_status(warp::reply::json(&record), http::StatusCode::CREATED),
+        Subscribed::Updated(record) => warp::reply::with_status(warp::reply::json(&record), http::StatusCode::OK),
+    })
 }
 
 async fn change_device(
+    install_id: Option<String>,
     device: DeviceSubscriptionChangeRequest,
     state_store: StateStore,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    state_store.change_subscription_from_device(&device).await;
-    Ok(warp::reply::with_status(
-        "Changed device",
-        http::StatusCode::ACCEPTED,
-    ))
-}
-
-async fn remove_device(
-    device: DeviceSubscription,
-    state_store: StateStore,
-) -> Result<impl warp::Reply, warp::Rejection> {
-    Ok(warp::reply::with_status(
-        "Removed device",
-        http::StatusCode::OK,
-    ))
-}
-
-fn json_body_new_device(
-) -> impl Filter<Extract = (DeviceSubscription,), Error = warp::Rejection> + Clone {
-    // When accepting a body, we want a JSON body
-    // (and to reject huge payloads)...
-    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
-}
-
-fn json_body_change_device(
-) -> impl Filter<Extract = (DeviceSubscriptionChangeRequest,), Error = warp::Rejection> + Clone {
-    // When accepting a body, we want a JSON body
-    // (and to reject huge payloads)...
-    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
-}
-
-/// get all the matches from a competition division pair
-async fn get_matches(
-    competition_division: &CompetitionDivisionPair,
-    robot_events_client: &RobotEvents
-) -> Option<Vec<robotevents::schema::Match>> {
-    let matches = robot_events_client.event_division_matches(competition_division.competition_id, competition_division.division_id, DivisionMatchesQuery::new().per_page(250)).await;
+    // an empty new token removes the subscription
+    if !device.new_device_token.is_empty() {
+        Platform::validate_any_token(&device.new_device_token)
+            .map_err(|e| ApiError::bad_request("invalid_device_token", e))?;
+    }
+    state_store.token_limiter.check(&device.old_device_token)?;
 
-    Some(matches.ok()?.data)
+    let records = state_store.change_subscription_from_device(&device, install_id.as_deref()).await?;
+    Ok(warp::reply::json(&json!({ "subscriptions": records })))
 }
 
+/// Poll every `poll_interval_secs` until shutdown. A cycle under way when the signal arrives
+/// runs to the end, pushes included.
 async fn poll(state_store: StateStore) {
-    loop {
-        // just print information about each subscription
+    for cycle in 1.. {
         let start_time = tokio::time::Instant::now();
 
-        state_store.update_all_subscriptions().await;
+        state_store.update_all_subscriptions()
+            .instrument(tracing::info_span!("poll_cycle", cycle))
+            .await;
 
-        sleep_until(start_time + tokio::time::Duration::from_secs(30)).await;
+        tokio::select! {
+            _ = sleep_until(start_time + state_store.config.poll_interval()) => {}
+            _ = state_store.shutdown.requested() => {
+                tracing::info!(cycle, "poll loop stopped");
+                return;
+            }
+        }
     }
 }
 
-#[tokio::main]
-async fn main() {
-    // let client = client::RobotEvents::new(token);
+fn routes(store: StateStore) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    // every route that changes subscriptions or webhooks, or reads a webhook, must be signed by a registered install
+    let signed = clientAuth::signed(store.clone());
+    let signed_subscription = clientAuth::signed_json::<DeviceSubscription>(store.clone());
+    let signed_change = clientAuth::signed_json::<DeviceSubscriptionChangeRequest>(store.clone());
+    let signed_webhook = clientAuth::signed_json::<webhooks::WebhookRequest>(store.clone());
+    // ...is limited per client IP, and is refused once shutdown begins
+    let per_ip = shutdown::accepting_changes(store.clone()).and(rateLimit::per_ip(store.clone()));
+    // new installs are limited more tightly still, since each one is remembered
+    let installs_per_ip = rateLimit::installs_per_ip(store.clone());
+    let store_filter = warp::any().map(move || store.clone());
 
-    let store = StateStore::new().unwrap();
-    let cloned_store = store.clone();
-    let store_filter = warp::any().map(move || cloned_store.clone());
+    let register_install = warp::post()
+        .and(warp::path!("v1" / "installs"))
+        .and(per_ip.clone())
+        .and(installs_per_ip)
+        .and(store_filter.clone())
+        .and_then(clientAuth::register_install);
 
     let add_items = warp::post()
         .and(warp::path("v1"))
         .and(warp::path("subscribe"))
         .and(warp::path::end())
-        .and(json_body_new_device())
+        .and(per_ip.clone())
+        .and(signed_subscription)
         .and(store_filter.clone())
         .and_then(add_device);
 
@@ -315,12 +1043,1096 @@ async fn main() {
         .and(warp::path("v1"))
         .and(warp::path("change"))
         .and(warp::path::end())
-        .and(json_body_change_device())
+        .and(per_ip.clone())
+        .and(signed_change)
         .and(store_filter.clone())
         .and_then(change_device);
 
-    join!(
-        warp::serve(add_items.or(change_device)).run(([0, 0, 0, 0], std::env::var("PORT").expect("PORT not set").parse().unwrap())),
-        poll(store.clone()),
-    );
+    let stream_sse = warp::get()
+        .and(warp::path!("v1" / "stream" / i32 / i32))
+        .and(warp::query::<divisionStream::StreamQuery>())
+        .and(store_filter.clone())
+        .and_then(divisionStream::stream_division_sse);
+
+    let stream_ws = warp::get()
+        .and(warp::path!("v1" / "ws" / i32 / i32))
+        .and(warp::query::<divisionStream::StreamQuery>())
+        .and(warp::ws())
+        .and(store_filter.clone())
+        .and_then(divisionStream::stream_division_ws);
+
+    let add_webhook = warp::post()
+        .and(warp::path!("v1" / "webhooks"))
+        .and(per_ip.clone())
+        .and(signed_webhook)
+        .and(store_filter.clone())
+        .and_then(webhooks::add_webhook);
+
+    // a webhook is only shown to, or changed by, the install that registered it
+    let get_webhook = warp::get()
+        .and(warp::path!("v1" / "webhooks" / String))
+        .and(signed.clone())
+        .and(store_filter.clone())
+        .and_then(webhooks::get_webhook);
+
+    let get_webhook_deliveries = warp::get()
+        .and(warp::path!("v1" / "webhooks" / String / "deliveries"))
+        .and(signed.clone())
+        .and(store_filter.clone())
+        .and_then(webhooks::get_deliveries);
+
+    let enable_webhook = warp::post()
+        .and(warp::path!("v1" / "webhooks" / String / "enable"))
+        .and(per_ip.clone())
+        .and(signed.clone())
+        .and(store_filter.clone())
+        .and_then(webhooks::enable_webhook);
+
+    let remove_webhook = warp::delete()
+        .and(warp::path!("v1" / "webhooks" / String))
+        .and(per_ip)
+        .and(signed)
+        .and(store_filter.clone())
+        .and_then(webhooks::remove_webhook);
+
+    let webhook_routes = add_webhook
+        .or(get_webhook)
+        .or(get_webhook_deliveries)
+        .or(enable_webhook)
+        .or(remove_webhook);
+
+    let rotate_apns_key = warp::post()
+        .and(warp::path!("v1" / "admin" / "apns" / "keys"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::content_length_limit(1024 * 16).and(warp::body::json()))
+        .and(store_filter.clone())
+        .and_then(admin::rotate_apns_key);
+
+    let admin_status = warp::get()
+        .and(warp::path!("v1" / "admin" / "status"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(store_filter.clone())
+        .and_then(admin::status);
+
+    let admin_subscriptions = warp::get()
+        .and(warp::path!("v1" / "admin" / "subscriptions"))
+        .and(warp::query::<admin::SubscriptionsQuery>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(store_filter.clone())
+        .and_then(admin::subscriptions);
+
+    let admin_content_state = warp::get()
+        .and(warp::path!("v1" / "admin" / "devices" / String / "content-state"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(store_filter.clone())
+        .and_then(admin::content_state);
+
+    let admin_test_push = warp::post()
+        .and(warp::path!("v1" / "admin" / "devices" / String / "test-push"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(store_filter.clone())
+        .and_then(admin::test_push);
+
+    let admin_remove_device = warp::delete()
+        .and(warp::path!("v1" / "admin" / "devices" / String))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(store_filter.clone())
+        .and_then(admin::remove_device);
+
+    let admin_repoll = warp::post()
+        .and(warp::path!("v1" / "admin" / "divisions" / i32 / i32 / "repoll"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(store_filter.clone())
+        .and_then(admin::repoll);
+
+    let admin_routes = rotate_apns_key
+        .or(admin_status)
+        .or(admin_subscriptions)
+        .or(admin_content_state)
+        .or(admin_test_push)
+        .or(admin_remove_device)
+        .or(admin_repoll);
+
+    let healthz = warp::get()
+        .and(warp::path("healthz"))
+        .and(warp::path::end())
+        .and_then(health::healthz);
+
+    let readyz = warp::get()
+        .and(warp::path("readyz"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(health::readyz);
+
+    let metrics = warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(metrics::metrics);
+
+    let division_matches = warp::get()
+        .and(warp::path!("v1" / "events" / i32 / "divisions" / i32 / "matches"))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(store_filter.clone())
+        .and_then(matchApi::division_matches);
+
+    let team_schedule = warp::get()
+        .and(warp::path!("v1" / "events" / i32 / "divisions" / i32 / "teams" / String / "schedule"))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(store_filter.clone())
+        .and_then(matchApi::team_schedule);
+
+    let division_corrections = warp::get()
+        .and(warp::path!("v1" / "events" / i32 / "divisions" / i32 / "corrections"))
+        .and(store_filter.clone())
+        .and_then(matchApi::division_corrections);
+
+    register_install
+        .or(add_items)
+        .or(change_device)
+        .or(stream_sse)
+        .or(stream_ws)
+        .or(webhook_routes)
+        .or(division_matches)
+        .or(team_schedule)
+        .or(division_corrections)
+        .or(admin_routes)
+        .or(healthz)
+        .or(readyz)
+        .or(metrics)
+        .recover(apiError::recover)
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Live Activity and push backend for EchoPulse")]
+struct Cli {
+    /// TOML settings file; defaults to `ECHOSCOPE_CONFIG`, then `echoscope.toml` if present
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serve the API and poll RobotEvents (the default)
+    Serve,
+    /// Replay a completed event as if it were live, pushing through the normal update path
+    Simulate(simulation::SimulateArgs),
+    /// Write a synthetic event's completed match list to a file
+    Generate(syntheticEvent::GenerateArgs),
+    /// Replay a synthetic event to thousands of fake devices on the mock APNs server
+    LoadTest(loadTest::LoadTestArgs),
+    /// Inspect or fix a running backend's state through its admin API
+    Admin(adminCli::AdminArgs),
+}
+
+/// Every `sweep_interval_secs`, drop expired subscriptions so their divisions stop being polled,
+/// and forget installs nobody has used in a long while.
+async fn sweep(state_store: StateStore) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(state_store.config.subscriptions.sweep_interval_secs));
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = state_store.shutdown.requested() => return,
+        }
+
+        let expired = state_store.remove_expired_subscriptions(Utc::now()).await;
+        if expired > 0 {
+            tracing::info!(expired, "swept expired subscriptions");
+        }
+        let forgotten = state_store.remove_idle_installs(Utc::now()).await;
+        if forgotten > 0 {
+            tracing::info!(forgotten, "forgot idle installs");
+        }
+    }
+}
+
+async fn serve(config_path: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let config = Config::load(config_path.as_deref())?;
+    logging::init(&config.logging);
+    let listen_addr = config.listen_addr;
+    let store = StateStore::new(config)?;
+    storage::load(&store).await?;
+
+    let signal_store = store.clone();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        tracing::info!("shutting down");
+        signal_store.shutdown.begin();
+    });
+
+    let (_, server) = warp::serve(routes(store.clone()))
+        .try_bind_with_graceful_shutdown(listen_addr, {
+            let shutdown = store.shutdown.clone();
+            async move { shutdown.requested().await }
+        })
+        .map_err(|e| format!("Unable to listen on {}: {}", listen_addr, e))?;
+
+    // everything below returns once shutdown begins and its in-flight work is done
+    let drained = async {
+        join!(server, poll(store.clone()), sweep(store.clone()), webhooks::run(store.clone()));
+    };
+    let deadline = async {
+        store.shutdown.requested().await;
+        tokio::time::sleep(store.config.shutdown_timeout()).await;
+    };
+
+    tokio::select! {
+        _ = drained => tracing::info!("drained"),
+        _ = deadline => tracing::warn!(
+            timeout_secs = store.config.shutdown_timeout_secs,
+            "shutdown deadline passed; abandoning in-flight requests and pushes"
+        ),
+    }
+    // let the other instances take our divisions now rather than when the leases run out
+    store.cluster.release_all().await;
+
+    storage::save(&store).await
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => exit_on_error(serve(cli.config).await),
+        Command::Simulate(args) => exit_on_error(simulation::run(args, cli.config).await),
+        Command::Generate(args) => exit_on_error(syntheticEvent::run_generate(args)),
+        Command::LoadTest(args) => exit_on_error(loadTest::run(args, cli.config).await),
+        Command::Admin(args) => exit_on_error(adminCli::run(args).await),
+    }
+}
+
+fn exit_on_error(result: Result<(), Box<dyn Error>>) {
+    if let Err(e) = result {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matchSource::{FixtureClock, FixtureSource, Recording, Snapshot};
+    use crate::mockApns::{MockApns, MockApnsConfig};
+    use robotevents::schema::Match;
+
+    pub(crate) const LIVE_ACTIVITY_TOKEN: &str = "0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9";
+    const NOTIFICATION_TOKEN: &str = "f9e8d7c6b5a40392817f6e5d4c3b2a1f0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b";
+
+    fn test_store(mock: &MockApns) -> StateStore {
+        test_store_with_source(mock, FixtureSource::new(Vec::new(), FixtureClock::manual()))
+    }
+
+    pub(crate) fn test_store_with_source(mock: &MockApns, match_source: impl MatchSource + 'static) -> StateStore {
+        StateStore::with_clients(
+            Config::default(),
+            PushProviders::new(mock.client(), None),
+            Arc::new(match_source),
+        )
+    }
+
+    pub(crate) fn test_match(id: i32, matchnum: i32, red: [&str; 2], blue: [&str; 2], scores: (i32, i32)) -> Match {
+        let teams = |names: [&str; 2]| names.iter().enumerate()
+            .map(|(i, name)| json!({ "team": { "id": i, "name": name, "code": null }, "sitting": false }))
+            .collect::<Vec<_>>();
+
+        serde_json::from_value(json!({
+            "id": id,
+            "event": { "id": 1, "name": "Test Event", "code": null },
+            "division": { "id": 1, "name": "Division 1", "code": null },
+            "round": 2,
+            "instance": 1,
+            "matchnum": matchnum,
+            "scheduled": "2025-03-08T09:00:00-06:00",
+            "started": null,
+            "field": "Field 1",
+            "scored": scores != (0, 0),
+            "name": format!("Qualifier #{}", matchnum),
+            "alliances": [
+                { "color": "blue", "score": scores.1, "teams": teams(blue) },
+                { "color": "red", "score": scores.0, "teams": teams(red) },
+            ]
+        }))
+        .unwrap()
+    }
+
+    pub(crate) fn token_pair(team_name: &str, device_token: &str) -> TeamTokenPair {
+        TeamTokenPair {
+            team_name: team_name.to_string(),
+            device_token: device_token.to_string(),
+            token_type: TokenType::LiveActivity,
+            platform: Platform::Ios,
+            app_id: config::DEFAULT_BUNDLE_ID.to_string(),
+            install_id: None,
+            created_at: Utc::now(),
+            last_pushed_at: None,
+            ttl_secs: 60 * 60,
+        }
+    }
+
+    /// A fresh install registered through the API, as `(install_id, secret)`.
+    async fn register_install(store: &StateStore) -> (String, String) {
+        let response = warp::test::request()
+            .method("POST")
+            .path("/v1/installs")
+            .reply(&routes(store.clone()))
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+
+        (body["install_id"].as_str().unwrap().to_string(), body["secret"].as_str().unwrap().to_string())
+    }
+
+    /// A POST of `body` to `path`, signed by `install`.
+    fn signed_post(install: &(String, String), path: &str, body: &serde_json::Value) -> warp::test::RequestBuilder {
+        let body = body.to_string();
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let signature = clientAuth::sign(&install.1, &timestamp, &http::Method::POST, path, body.as_bytes());
+
+        warp::test::request()
+            .method("POST")
+            .path(path)
+            .header("x-echoscope-install", &install.0)
+            .header("x-echoscope-timestamp", &timestamp)
+            .header("x-echoscope-signature", format!("sha256={}", signature))
+            .body(body)
+    }
+
+    pub(crate) async fn subscribe(store: &StateStore, token: &str, token_type: &str) {
+        let install = register_install(store).await;
+        let body = json!({
+            "competition_id": 1,
+            "division_id": 1,
+            "device_token": token,
+            "watch_team": "5839a",
+            "token_type": token_type,
+        });
+        let response = signed_post(&install, "/v1/subscribe", &body)
+            .reply(&routes(store.clone()))
+            .await;
+
+        assert_eq!(response.status(), http::StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn subscribe_then_match_update_pushes_to_every_channel() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = test_store(&mock);
+        let division = CompetitionDivisionPair::new(1, 1);
+
+        subscribe(&store, LIVE_ACTIVITY_TOKEN, "live_activity").await;
+        subscribe(&store, NOTIFICATION_TOKEN, "notification").await;
+
+        store.apply_matches(&division, vec![
+            test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (0, 0)),
+            test_match(2, 2, ["4444E", "5555F"], ["6666G", "7777H"], (0, 0)),
+        ]).await;
+
+        let deliveries = mock.deliveries();
+        assert_eq!(deliveries.len(), 2);
+
+        let live_activity = deliveries.iter().find(|d| d.device_token == LIVE_ACTIVITY_TOKEN).unwrap();
+        assert_eq!(live_activity.push_type, "liveactivity");
+        assert_eq!(live_activity.payload["aps"]["content-state"]["teamNextMatch"]["name"], "Q 1");
+
+        let background = deliveries.iter().find(|d| d.device_token == NOTIFICATION_TOKEN).unwrap();
+        assert_eq!(background.push_type, "background");
+
+        // the team's match gets scored: the notification token now gets a banner
+        store.apply_matches(&division, vec![
+            test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (120, 98)),
+            test_match(2, 2, ["4444E", "5555F"], ["6666G", "7777H"], (0, 0)),
+        ]).await;
+
+        let deliveries = mock.deliveries();
+        assert_eq!(deliveries.len(), 4);
+
+        let alert = deliveries.iter().find(|d| d.push_type == "alert").unwrap();
+        assert_eq!(alert.device_token, NOTIFICATION_TOKEN);
+        assert_eq!(alert.payload["aps"]["alert"]["title"], "Q 1: 5839A won");
+        assert_eq!(alert.payload["aps"]["alert"]["body"], "5839A & 1234B 120 - 98 2222C & 3333D");
+    }
+
+    #[tokio::test]
+    async fn subscriptions_are_routed_to_their_app() {
+        const BETA_APP: &str = "net.dickhans.EchoPulse.beta";
+
+        let mock = MockApns::start(MockApnsConfig {
+            bundle_ids: vec![config::DEFAULT_BUNDLE_ID.to_string(), BETA_APP.to_string()],
+            ..MockApnsConfig::default()
+        });
+        let store = StateStore::with_clients(
+            Config::default(),
+            PushProviders::new(mock.client(), None).with_app(mock.client_for(BETA_APP)),
+            Arc::new(FixtureSource::new(Vec::new(), FixtureClock::manual())),
+        );
+        let routes = routes(store.clone());
+        let install = register_install(&store).await;
+
+        let subscribe = |token: &str, app_id: &str| {
+            let body = json!({
+                "competition_id": 1,
+                "division_id": 1,
+                "device_token": token,
+                "watch_team": "5839a",
+                "app_id": app_id,
+            });
+            signed_post(&install, "/v1/subscribe", &body).reply(&routes)
+        };
+
+        assert_eq!(subscribe(LIVE_ACTIVITY_TOKEN, BETA_APP).await.status(), http::StatusCode::CREATED);
+        assert_eq!(subscribe(NOTIFICATION_TOKEN, "net.dickhans.Unknown").await.status(), http::StatusCode::BAD_REQUEST);
+
+        store.apply_matches(&CompetitionDivisionPair::new(1, 1), vec![
+            test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (0, 0)),
+        ]).await;
+
+        let deliveries = mock.deliveries();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].topic, format!("{}.push-type.liveactivity", BETA_APP));
+    }
+
+    #[tokio::test]
+    async fn only_the_registering_install_can_move_a_token() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = test_store(&mock);
+        let routes = routes(store.clone());
+        let owner = register_install(&store).await;
+        let stranger = register_install(&store).await;
+
+        let subscription = json!({
+            "competition_id": 1,
+            "division_id": 1,
+            "device_token": LIVE_ACTIVITY_TOKEN,
+            "watch_team": "5839a",
+        });
+        let unsigned = warp::test::request()
+            .method("POST")
+            .path("/v1/subscribe")
+            .json(&subscription)
+            .reply(&routes)
+            .await;
+        assert_eq!(unsigned.status(), http::StatusCode::UNAUTHORIZED);
+
+        let forged = signed_post(&(owner.0.clone(), stranger.1.clone()), "/v1/subscribe", &subscription)
+            .reply(&routes)
+            .await;
+        assert_eq!(forged.status(), http::StatusCode::UNAUTHORIZED);
+
+        let subscribed = signed_post(&owner, "/v1/subscribe", &subscription).reply(&routes).await;
+        assert_eq!(subscribed.status(), http::StatusCode::CREATED);
+
+        let device_tokens = || async {
+            store.subscriptions.read().await[&CompetitionDivisionPair::new(1, 1)].iter()
+                .map(|pair| pair.device_token.clone())
+                .collect::<Vec<_>>()
+        };
+        let change = json!({ "old_device_token": LIVE_ACTIVITY_TOKEN, "new_device_token": NOTIFICATION_TOKEN });
+
+        let stolen = signed_post(&stranger, "/v1/change", &change).reply(&routes).await;
+        assert_eq!(stolen.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(device_tokens().await, vec![LIVE_ACTIVITY_TOKEN]);
+
+        signed_post(&owner, "/v1/change", &change).reply(&routes).await;
+        assert_eq!(device_tokens().await, vec![NOTIFICATION_TOKEN]);
+    }
+
+    #[tokio::test]
+    async fn installs_are_limited_replays_refused_and_idle_ones_forgotten() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let mut config = Config::default();
+        config.limits.installs_per_hour_per_ip = 2;
+        let store = StateStore::with_clients(
+            config,
+            PushProviders::new(mock.client(), None),
+            Arc::new(FixtureSource::new(Vec::new(), FixtureClock::manual())),
+        );
+        let routes = routes(store.clone());
+
+        let subscriber = register_install(&store).await;
+        let idle = register_install(&store).await;
+        let refused = warp::test::request().method("POST").path("/v1/installs").reply(&routes).await;
+        assert_eq!(refused.status(), http::StatusCode::TOO_MANY_REQUESTS);
+
+        let subscription = json!({
+            "competition_id": 1,
+            "division_id": 1,
+            "device_token": LIVE_ACTIVITY_TOKEN,
+            "watch_team": "5839a",
+        });
+        let request = signed_post(&subscriber, "/v1/subscribe", &subscription);
+        let replay = signed_post(&subscriber, "/v1/subscribe", &subscription);
+        assert_eq!(request.reply(&routes).await.status(), http::StatusCode::CREATED);
+        assert_eq!(replay.reply(&routes).await.status(), http::StatusCode::UNAUTHORIZED);
+
+        // long after, the install still owning a subscription is kept
+        let later = Utc::now() + chrono::Duration::seconds(store.config.subscriptions.install_ttl_secs as i64 + 1);
+        assert_eq!(store.remove_idle_installs(later).await, 1);
+        let installs = store.installs.snapshot().await;
+        assert!(installs.contains_key(&subscriber.0) && !installs.contains_key(&idle.0));
+    }
+
+    #[tokio::test]
+    async fn abusive_subscriptions_are_turned_away() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let mut config = Config::default();
+        config.limits.requests_per_minute_per_token = 1;
+        config.limits.max_divisions = 1;
+        let store = StateStore::with_clients(
+            config,
+            PushProviders::new(mock.client(), None),
+            Arc::new(FixtureSource::new(Vec::new(), FixtureClock::manual())),
+        );
+        let routes = routes(store.clone());
+        let install = register_install(&store).await;
+        let subscription = |division_id: i32, token: &str| json!({
+            "competition_id": 1,
+            "division_id": division_id,
+            "device_token": token,
+            "watch_team": "5839a",
+        });
+
+        let malformed = signed_post(&install, "/v1/subscribe", &subscription(1, "not-a-token")).reply(&routes).await;
+        assert_eq!(malformed.status(), http::StatusCode::BAD_REQUEST);
+
+        let first = signed_post(&install, "/v1/subscribe", &subscription(1, LIVE_ACTIVITY_TOKEN)).reply(&routes).await;
+        assert_eq!(first.status(), http::StatusCode::CREATED);
+
+        // a fresh request rather than a replay of the first, which would be refused outright
+        let mut resubscribe = subscription(1, LIVE_ACTIVITY_TOKEN);
+        resubscribe["watch_team"] = json!("5839A");
+        let again = signed_post(&install, "/v1/subscribe", &resubscribe).reply(&routes).await;
+        assert_eq!(again.status(), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(again.headers()["retry-after"], "60");
+
+        // one division is all this backend will poll
+        let full = signed_post(&install, "/v1/subscribe", &subscription(2, NOTIFICATION_TOKEN)).reply(&routes).await;
+        assert_eq!(full.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+        assert!(full.headers().contains_key("retry-after"));
+
+        assert_eq!(store.subscriptions.read().await[&CompetitionDivisionPair::new(1, 1)].len(), 1);
+    }
+
+    /// Valid for every division, but slow to say so, like RobotEvents.
+    #[derive(Debug)]
+    struct SlowToCheck;
+
+    #[async_trait::async_trait]
+    impl MatchSource for SlowToCheck {
+        async fn division_matches(&self, _: &CompetitionDivisionPair) -> Result<Vec<Match>, matchSource::MatchSourceError> {
+            Ok(Vec::new())
+        }
+
+        async fn check_division(&self, _: &CompetitionDivisionPair) -> Result<DivisionCheck, matchSource::MatchSourceError> {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            Ok(DivisionCheck::Valid)
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_subscriptions_stay_within_the_division_cap() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let mut config = Config::default();
+        config.limits.max_divisions = 2;
+        let store = StateStore::with_clients(config, PushProviders::new(mock.client(), None), Arc::new(SlowToCheck));
+        let routes = routes(store.clone());
+        let install = register_install(&store).await;
+
+        // every request passes the early check while the others are still being validated
+        let responses = futures_util::future::join_all((1..=5).map(|division_id| {
+            let body = json!({
+                "competition_id": 1,
+                "division_id": division_id,
+                "device_token": format!("{:064x}", division_id),
+                "watch_team": "5839a",
+            });
+            let request = signed_post(&install, "/v1/subscribe", &body);
+            let routes = routes.clone();
+            async move { request.reply(&routes).await.status() }
+        }))
+        .await;
+
+        let created = responses.iter().filter(|status| **status == http::StatusCode::CREATED).count();
+        let refused = responses.iter().filter(|status| **status == http::StatusCode::SERVICE_UNAVAILABLE).count();
+        assert_eq!((created, refused), (2, 3));
+        assert_eq!(store.subscriptions.read().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_changes_and_the_poll_loop() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = test_store(&mock);
+        let routes = routes(store.clone());
+        let install = register_install(&store).await;
+        let poll_loop = tokio::spawn(poll(store.clone()));
+
+        store.shutdown.begin();
+        tokio::time::timeout(std::time::Duration::from_secs(5), poll_loop).await
+            .expect("the poll loop stops at shutdown")
+            .unwrap();
+
+        let subscription = json!({
+            "competition_id": 1,
+            "division_id": 1,
+            "device_token": LIVE_ACTIVITY_TOKEN,
+            "watch_team": "5839a",
+        });
+        let response = signed_post(&install, "/v1/subscribe", &subscription).reply(&routes).await;
+        assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "5");
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["code"], "shutting_down");
+
+        let ready = warp::test::request().path("/readyz").reply(&routes).await;
+        assert_eq!(ready.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn resubscribing_updates_and_changes_rename_everywhere() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = test_store(&mock);
+        let routes = routes(store.clone());
+        let install = register_install(&store).await;
+        let subscription = |division_id: i32, team: &str| json!({
+            "competition_id": 1,
+            "division_id": division_id,
+            "device_token": NOTIFICATION_TOKEN,
+            "watch_team": team,
+            "token_type": "notification",
+        });
+        let body = |response: &warp::http::Response<warp::hyper::body::Bytes>| -> serde_json::Value {
+            serde_json::from_slice(response.body()).unwrap()
+        };
+
+        let created = signed_post(&install, "/v1/subscribe", &subscription(1, "5839a")).reply(&routes).await;
+        assert_eq!(created.status(), http::StatusCode::CREATED);
+        let retried = signed_post(&install, "/v1/subscribe", &subscription(1, "1234b")).reply(&routes).await;
+        assert_eq!(retried.status(), http::StatusCode::OK);
+        assert_eq!(body(&retried)["watch_team"], "1234b");
+        signed_post(&install, "/v1/subscribe", &subscription(2, "5839a")).reply(&routes).await;
+
+        let division = |division_id| CompetitionDivisionPair::new(1, division_id);
+        assert_eq!(store.subscriptions.read().await[&division(1)].len(), 1);
+
+        let change = json!({ "old_device_token": NOTIFICATION_TOKEN, "new_device_token": LIVE_ACTIVITY_TOKEN });
+        let changed = signed_post(&install, "/v1/change", &change).reply(&routes).await;
+        assert_eq!(changed.status(), http::StatusCode::OK);
+        assert_eq!(body(&changed)["subscriptions"].as_array().unwrap().len(), 2);
+        for division_id in [1, 2] {
+            let subscriptions = store.subscriptions.read().await;
+            let tokens: Vec<_> = subscriptions[&division(division_id)].iter().map(|pair| pair.device_token.as_str()).collect();
+            assert_eq!(tokens, vec![LIVE_ACTIVITY_TOKEN]);
+        }
+
+        let remove = json!({ "old_device_token": LIVE_ACTIVITY_TOKEN, "new_device_token": "" });
+        let removed = signed_post(&install, "/v1/change", &remove).reply(&routes).await;
+        assert_eq!(removed.status(), http::StatusCode::OK);
+        assert!(store.subscriptions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn subscriptions_expire_and_their_divisions_stop_polling() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = test_store(&mock);
+        let routes = routes(store.clone());
+        let install = register_install(&store).await;
+        let subscription = |division_id: i32, token: &str, token_type: &str, ttl_secs: u64| json!({
+            "competition_id": 1,
+            "division_id": division_id,
+            "device_token": token,
+            "watch_team": "5839a",
+            "token_type": token_type,
+            "ttl_secs": ttl_secs,
+        });
+
+        let too_long = signed_post(&install, "/v1/subscribe", &subscription(1, LIVE_ACTIVITY_TOKEN, "live_activity", 30 * 24 * 60 * 60))
+            .reply(&routes)
+            .await;
+        assert_eq!(too_long.status(), http::StatusCode::BAD_REQUEST);
+
+        signed_post(&install, "/v1/subscribe", &subscription(1, LIVE_ACTIVITY_TOKEN, "live_activity", 8 * 60 * 60)).reply(&routes).await;
+        signed_post(&install, "/v1/subscribe", &subscription(2, NOTIFICATION_TOKEN, "notification", 60 * 60)).reply(&routes).await;
+
+        let notified = CompetitionDivisionPair::new(1, 2);
+        store.apply_matches(&notified, vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (0, 0))]).await;
+        let pushed_at = store.subscriptions.read().await[&notified][0].last_pushed_at.unwrap();
+
+        // the notification subscription's hour runs from its last push; the Live Activity's from creation
+        let start = Utc::now();
+        assert_eq!(store.remove_expired_subscriptions(pushed_at + chrono::Duration::minutes(59)).await, 0);
+        assert_eq!(store.remove_expired_subscriptions(start + chrono::Duration::hours(2)).await, 1);
+        assert_eq!(store.polled_divisions().await, vec![CompetitionDivisionPair::new(1, 1)]);
+        assert!(!store.matches.read().await.contains_key(&notified));
+
+        assert_eq!(store.remove_expired_subscriptions(start + chrono::Duration::hours(9)).await, 1);
+        assert!(store.subscriptions.read().await.is_empty());
+    }
+
+    /// RobotEvents as far as subscribe-time validation is concerned: event 1 has only division 1
+    /// and event 2 is from last season.
+    #[derive(Debug)]
+    pub(crate) struct KnownDivisions;
+
+    #[async_trait::async_trait]
+    impl MatchSource for KnownDivisions {
+        async fn division_matches(&self, _: &CompetitionDivisionPair) -> Result<Vec<Match>, matchSource::MatchSourceError> {
+            Ok(Vec::new())
+        }
+
+        async fn check_division(&self, competition_division: &CompetitionDivisionPair) -> Result<DivisionCheck, matchSource::MatchSourceError> {
+            Ok(match (competition_division.competition_id, competition_division.division_id) {
+                (1, 1) => DivisionCheck::Valid,
+                (1, _) => DivisionCheck::UnknownDivision,
+                (2, _) => DivisionCheck::PastSeason { season: "VRC 2023-2024: Over Under".to_string() },
+                _ => DivisionCheck::UnknownEvent,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn bad_subscriptions_get_json_errors() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = test_store_with_source(&mock, KnownDivisions);
+        let routes = routes(store.clone());
+        let install = register_install(&store).await;
+        let subscribe = |competition_id: i32, division_id: i32| {
+            let body = json!({
+                "competition_id": competition_id,
+                "division_id": division_id,
+                "device_token": LIVE_ACTIVITY_TOKEN,
+                "watch_team": "5839a",
+            });
+            signed_post(&install, "/v1/subscribe", &body).reply(&routes)
+        };
+        let error = |response: &warp::http::Response<warp::hyper::body::Bytes>| -> (http::StatusCode, String) {
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            (response.status(), body["code"].as_str().unwrap().to_string())
+        };
+
+        let missing_team = signed_post(&install, "/v1/subscribe", &json!({ "competition_id": 1, "division_id": 1, "device_token": LIVE_ACTIVITY_TOKEN }))
+            .reply(&routes)
+            .await;
+        assert_eq!(error(&missing_team), (http::StatusCode::BAD_REQUEST, "invalid_body".to_string()));
+
+        assert_eq!(error(&subscribe(9, 1).await), (http::StatusCode::NOT_FOUND, "unknown_event".to_string()));
+        assert_eq!(error(&subscribe(1, 2).await), (http::StatusCode::NOT_FOUND, "unknown_division".to_string()));
+        assert_eq!(error(&subscribe(2, 1).await), (http::StatusCode::BAD_REQUEST, "past_season".to_string()));
+        assert!(store.subscriptions.read().await.is_empty());
+
+        assert_eq!(subscribe(1, 1).await.status(), http::StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn unchanged_matches_do_not_push() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = test_store(&mock);
+        let division = CompetitionDivisionPair::new(1, 1);
+        let matches = vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (0, 0))];
+
+        subscribe(&store, LIVE_ACTIVITY_TOKEN, "live_activity").await;
+
+        store.apply_matches(&division, matches.clone()).await;
+        store.apply_matches(&division, matches).await;
+
+        assert_eq!(mock.deliveries().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn matches_already_played_are_a_baseline_not_news() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let clock = FixtureClock::manual();
+        let played = test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (80, 95));
+        let recording = Recording {
+            competition_id: 1,
+            division_id: 1,
+            snapshots: vec![
+                Snapshot { offset_secs: 0, matches: vec![played.clone(), test_match(2, 2, ["5839A", "4444E"], ["5555F", "6666G"], (0, 0))] },
+                Snapshot { offset_secs: 300, matches: vec![played, test_match(2, 2, ["5839A", "4444E"], ["5555F", "6666G"], (60, 40))] },
+            ],
+        };
+        let store = StateStore {
+            webhooks: webhooks::WebhookRegistry::local(),
+            ..test_store_with_source(&mock, FixtureSource::new(vec![recording], clock.clone()))
+        };
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let listener = {
+            let received = received.clone();
+            warp::post().and(warp::body::bytes()).map(move |body: warp::hyper::body::Bytes| {
+                received.lock().unwrap().push(String::from_utf8_lossy(&body).to_string());
+                "ok"
+            })
+        };
+        let (addr, server) = warp::serve(listener).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        tokio::spawn(webhooks::run(store.clone()));
+
+        // the team's first match was scored before anyone here was watching the division
+        subscribe(&store, NOTIFICATION_TOKEN, "notification").await;
+        let install = register_install(&store).await;
+        let webhook = json!({ "url": format!("http://{}/hook", addr), "competition_id": 1, "division_id": 1 });
+        let registered = signed_post(&install, "/v1/webhooks", &webhook).reply(&routes(store.clone())).await;
+        assert_eq!(registered.status(), http::StatusCode::CREATED);
+
+        store.update_all_subscriptions().await;
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(mock.deliveries().iter().all(|d| d.push_type == "background"));
+        assert!(received.lock().unwrap().is_empty());
+
+        // what happens from then on is news
+        clock.advance(300);
+        store.update_all_subscriptions().await;
+        let alert = mock.deliveries().into_iter().find(|d| d.push_type == "alert").unwrap();
+        assert_eq!(alert.payload["aps"]["alert"]["title"], "Q 2: 5839A won");
+        for _ in 0..100 {
+            if !received.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].contains("score_posted"));
+    }
+
+    #[tokio::test]
+    async fn corrected_scores_are_recorded_flagged_and_announced() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = test_store(&mock);
+        let division = CompetitionDivisionPair::new(1, 1);
+
+        subscribe(&store, LIVE_ACTIVITY_TOKEN, "live_activity").await;
+        subscribe(&store, NOTIFICATION_TOKEN, "notification").await;
+
+        store.apply_matches(&division, vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (80, 95))]).await;
+        assert!(store.corrections.corrections(&division).is_empty());
+
+        // the referee fixes the red score, turning the loss into a win
+        let before = Utc::now();
+        store.apply_matches(&division, vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (100, 95))]).await;
+
+        let corrections = store.corrections.corrections(&division);
+        assert_eq!(corrections.len(), 1);
+        assert_eq!((corrections[0].match_id, corrections[0].name.as_str()), (1, "Q 1"));
+        assert_eq!((corrections[0].previous.red, corrections[0].corrected.red), (80, 100));
+        assert!(corrections[0].corrected_at >= before);
+
+        let deliveries = mock.deliveries();
+        let live_activity = deliveries.iter().rfind(|d| d.device_token == LIVE_ACTIVITY_TOKEN).unwrap();
+        assert_eq!(live_activity.payload["aps"]["content-state"]["lastMatch"]["corrected"], true);
+
+        let alert = deliveries.iter().rfind(|d| d.push_type == "alert").unwrap();
+        assert_eq!(alert.payload["aps"]["alert"]["title"], "Q 1: score corrected");
+        assert_eq!(alert.payload["aps"]["alert"]["body"], "5839A & 1234B 100 - 95 2222C & 3333D (was 80 - 95)");
+
+        let response = warp::test::request()
+            .path("/v1/events/1/divisions/1/corrections")
+            .reply(&routes(store.clone()))
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body[0]["match"], "Q 1");
+        assert_eq!(body[0]["previous"], json!({ "red": 80, "blue": 95 }));
+
+        // without correction alerts the content state is still flagged, but there's no banner
+        let mut config = Config::default();
+        config.subscriptions.correction_alerts = false;
+        let quiet = StateStore::with_clients(
+            config,
+            PushProviders::new(mock.client(), None),
+            Arc::new(FixtureSource::new(Vec::new(), FixtureClock::manual())),
+        );
+        subscribe(&quiet, NOTIFICATION_TOKEN, "notification").await;
+        quiet.apply_matches(&division, vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (80, 95))]).await;
+        let alerts = mock.deliveries().iter().filter(|d| d.push_type == "alert").count();
+        quiet.apply_matches(&division, vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (100, 95))]).await;
+        assert_eq!(mock.deliveries().iter().filter(|d| d.push_type == "alert").count(), alerts);
+    }
+
+    #[tokio::test]
+    async fn metrics_report_subscriptions_and_pushes() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = test_store(&mock);
+
+        subscribe(&store, LIVE_ACTIVITY_TOKEN, "live_activity").await;
+        subscribe(&store, NOTIFICATION_TOKEN, "notification").await;
+        store.apply_matches(&CompetitionDivisionPair::new(1, 1), vec![
+            test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (0, 0)),
+        ]).await;
+
+        let response = warp::test::request()
+            .path("/metrics")
+            .reply(&routes(store.clone()))
+            .await;
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        let body = String::from_utf8_lossy(response.body());
+        assert!(body.contains(r#"echoscope_active_subscriptions{competition_id="1",division_id="1"} 2"#));
+        assert!(body.contains(r#"echoscope_apns_pushes_total{push_type="liveactivity",reason="",result="sent"}"#));
+        assert!(body.contains(r#"echoscope_match_data_age_seconds{competition_id="1",division_id="1"}"#));
+        assert!(body.contains(r#"echoscope_token_refreshes_total{provider="apns"}"#));
+    }
+
+    #[tokio::test]
+    async fn poll_replays_recorded_event() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let clock = FixtureClock::manual();
+        let recording = Recording {
+            competition_id: 1,
+            division_id: 1,
+            snapshots: vec![
+                Snapshot { offset_secs: 0, matches: vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (0, 0))] },
+                Snapshot { offset_secs: 300, matches: vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (80, 95))] },
+            ],
+        };
+        let store = test_store_with_source(&mock, FixtureSource::new(vec![recording], clock.clone()));
+
+        subscribe(&store, NOTIFICATION_TOKEN, "notification").await;
+
+        store.update_all_subscriptions().await;
+        store.update_all_subscriptions().await;
+        assert_eq!(mock.deliveries().len(), 1);
+        assert_eq!(mock.deliveries()[0].push_type, "background");
+
+        clock.advance(300);
+        store.update_all_subscriptions().await;
+
+        let deliveries = mock.deliveries();
+        assert_eq!(deliveries.len(), 2);
+        assert_eq!(deliveries[1].payload["aps"]["alert"]["title"], "Q 1: 5839A lost");
+    }
+
+    #[tokio::test]
+    async fn instances_share_subscriptions_and_split_the_pushing() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let clock = FixtureClock::manual();
+        let recording = |division_id: i32| Recording {
+            competition_id: 1,
+            division_id,
+            snapshots: vec![
+                Snapshot { offset_secs: 0, matches: vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (0, 0))] },
+                Snapshot { offset_secs: 300, matches: vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (80, 95))] },
+            ],
+        };
+        let shared: Arc<dyn cluster::ClusterStore> = Arc::new(cluster::MemoryCluster::default());
+        let instance = |instance_id: &str| {
+            let cluster = cluster::Cluster::new(shared.clone(), instance_id, std::time::Duration::from_secs(90));
+            StateStore {
+                webhooks: webhooks::WebhookRegistry::local().with_cluster(cluster.clone()),
+                cluster,
+                ..test_store_with_source(&mock, FixtureSource::new(vec![recording(1), recording(2)], clock.clone()))
+            }
+        };
+        let (a, b) = (instance("a"), instance("b"));
+
+        // an install registered with one instance signs requests the other accepts
+        let install = register_install(&a).await;
+        for (division_id, token) in [(1, LIVE_ACTIVITY_TOKEN), (2, NOTIFICATION_TOKEN)] {
+            let body = json!({
+                "competition_id": 1,
+                "division_id": division_id,
+                "device_token": token,
+                "watch_team": "5839a",
+            });
+            let response = signed_post(&install, "/v1/subscribe", &body).reply(&routes(b.clone())).await;
+            assert_eq!(response.status(), http::StatusCode::CREATED);
+        }
+
+        // a starts alone and takes both divisions; once b is up they split them, and b picks
+        // up where a left off rather than pushing the same matches again
+        a.update_all_subscriptions().await;
+        assert_eq!(a.subscription_records(None, None).await.len(), 2);
+        assert_eq!(mock.deliveries().len(), 2);
+        b.update_all_subscriptions().await;
+        a.update_all_subscriptions().await;
+        b.update_all_subscriptions().await;
+        assert_eq!(mock.deliveries().len(), 2);
+        for division_id in [1, 2] {
+            let division = CompetitionDivisionPair::new(1, division_id);
+            assert_ne!(a.cluster.owns(&division), b.cluster.owns(&division));
+        }
+
+        // each score goes out once, from whichever instance owns the division
+        clock.advance(300);
+        a.update_all_subscriptions().await;
+        b.update_all_subscriptions().await;
+        let deliveries = mock.deliveries();
+        assert_eq!(deliveries.len(), 4);
+        for token in [LIVE_ACTIVITY_TOKEN, NOTIFICATION_TOKEN] {
+            assert_eq!(deliveries.iter().filter(|delivery| delivery.device_token == token).count(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn instances_share_webhooks_and_forget_idle_installs_together() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let shared: Arc<dyn cluster::ClusterStore> = Arc::new(cluster::MemoryCluster::default());
+        let instance = |instance_id: &str| {
+            let cluster = cluster::Cluster::new(shared.clone(), instance_id, std::time::Duration::from_secs(90));
+            StateStore {
+                webhooks: webhooks::WebhookRegistry::local().with_cluster(cluster.clone()),
+                cluster,
+                ..test_store(&mock)
+            }
+        };
+        let (a, b) = (instance("a"), instance("b"));
+        let install = register_install(&a).await;
+
+        let webhook = json!({ "url": "http://127.0.0.1:9/hook", "competition_id": 1, "division_id": 1 }).to_string();
+        let timestamp = Utc::now().timestamp().to_string();
+        let signature = clientAuth::sign(&install.1, &timestamp, &http::Method::POST, "/v1/webhooks", webhook.as_bytes());
+        let register = |store: &StateStore| {
+            let request = warp::test::request()
+                .method("POST")
+                .path("/v1/webhooks")
+                .header("x-echoscope-install", &install.0)
+                .header("x-echoscope-timestamp", &timestamp)
+                .header("x-echoscope-signature", format!("sha256={}", signature))
+                .body(webhook.clone());
+            let routes = routes(store.clone());
+            async move { request.reply(&routes).await }
+        };
+        let registered = register(&a).await;
+        assert_eq!(registered.status(), http::StatusCode::CREATED);
+
+        // the same signed request replayed against another instance is turned away there too
+        let replayed = register(&b).await;
+        assert_eq!(replayed.status(), http::StatusCode::UNAUTHORIZED);
+        let id = serde_json::from_slice::<serde_json::Value>(registered.body()).unwrap()["id"].as_str().unwrap().to_string();
+
+        // signed requests without a body, to the webhook on `store`; each is signed a second
+        // apart, as otherwise the same request twice is a replay
+        let sent = std::sync::atomic::AtomicI64::new(0);
+        let signed = |store: &StateStore, method: &str| {
+            let path = format!("/v1/webhooks/{}", id);
+            let timestamp = (Utc::now().timestamp() - sent.fetch_add(1, std::sync::atomic::Ordering::Relaxed)).to_string();
+            let method = http::Method::from_bytes(method.as_bytes()).unwrap();
+            let signature = clientAuth::sign(&install.1, &timestamp, &method, &path, b"");
+            let request = warp::test::request()
+                .method(method.as_str())
+                .path(&path)
+                .header("x-echoscope-install", &install.0)
+                .header("x-echoscope-timestamp", &timestamp)
+                .header("x-echoscope-signature", format!("sha256={}", signature));
+            let routes = routes(store.clone());
+            async move { request.reply(&routes).await }
+        };
+
+        // b polls for it and sees it, and a no longer does once b removes it
+        b.update_all_subscriptions().await;
+        assert_eq!(b.polled_divisions().await, vec![CompetitionDivisionPair::new(1, 1)]);
+        assert_eq!(signed(&b, "GET").await.status(), http::StatusCode::OK);
+        assert_eq!(signed(&b, "DELETE").await.status(), http::StatusCode::OK);
+        assert_eq!(signed(&a, "GET").await.status(), http::StatusCode::NOT_FOUND);
+        assert!(a.polled_divisions().await.is_empty());
+
+        // with nothing left to own, the install is forgotten by the cluster too, so an instance
+        // that hasn't seen it yet can't fetch it back
+        let later = Utc::now() + chrono::Duration::seconds(a.config.subscriptions.install_ttl_secs as i64 + 60);
+        assert_eq!(a.remove_idle_installs(later).await, 1);
+        let refused = signed(&instance("c"), "GET").await;
+        assert_eq!(refused.status(), http::StatusCode::UNAUTHORIZED);
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(refused.body()).unwrap()["error"], "unknown install");
+    }
 }
@@ -0,0 +1,200 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use warp::http::{self, Response};
+use warp::hyper::Body;
+use warp::Reply;
+use crate::apiError::ApiError;
+use crate::competitionAttributes::DisplayMatch;
+use crate::{CompetitionDivisionPair, StateStore};
+
+/// `GET /v1/events/{event}/divisions/{division}/matches`
+pub async fn division_matches(
+    competition_id: i32,
+    division_id: i32,
+    if_none_match: Option<String>,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let competition_division = CompetitionDivisionPair::new(competition_id, division_id);
+    ensure_polled(&state_store, &competition_division).await?;
+
+    Ok(match state_store.cached_matches(&competition_division).await {
+        Some(matches) => {
+            let corrected = state_store.corrections.corrected_matches(&competition_division);
+            let display_matches: Vec<DisplayMatch> = matches.iter().map(|m| DisplayMatch::flagged(m, &corrected)).collect();
+            json_with_etag(&display_matches, if_none_match.as_deref())
+        }
+        None => unavailable(&competition_division),
+    })
+}
+
+/// `GET /v1/events/{event}/divisions/{division}/teams/{team}/schedule`
+pub async fn team_schedule(
+    competition_id: i32,
+    division_id: i32,
+    team: String,
+    if_none_match: Option<String>,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let competition_division = CompetitionDivisionPair::new(competition_id, division_id);
+    ensure_polled(&state_store, &competition_division).await?;
+
+    Ok(match state_store.cached_matches(&competition_division).await {
+        Some(matches) => {
+            let corrected = state_store.corrections.corrected_matches(&competition_division);
+            let schedule: Vec<DisplayMatch> = matches.iter()
+                .map(|m| DisplayMatch::flagged(m, &corrected))
+                .filter(|display_match| display_match.involves_team(&team))
+                .collect();
+            json_with_etag(&schedule, if_none_match.as_deref())
+        }
+        None => unavailable(&competition_division),
+    })
+}
+
+/// `GET /v1/events/{event}/divisions/{division}/corrections`: every score changed after
+/// posting since the division was first polled, oldest first.
+pub async fn division_corrections(
+    competition_id: i32,
+    division_id: i32,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let competition_division = CompetitionDivisionPair::new(competition_id, division_id);
+    Ok(warp::reply::json(&state_store.corrections.corrections(&competition_division)))
+}
+
+/// Only divisions something already polls are served; anything else would have these public
+/// routes fetching from RobotEvents on anyone's behalf.
+async fn ensure_polled(state_store: &StateStore, competition_division: &CompetitionDivisionPair) -> Result<(), ApiError> {
+    if state_store.polled_divisions().await.contains(competition_division) {
+        return Ok(());
+    }
+
+    Err(ApiError::not_found(
+        "division_not_polled",
+        format!(
+            "event {} division {} isn't being followed; subscribe or open a stream for it first",
+            competition_division.competition_id, competition_division.division_id
+        ),
+    ))
+}
+
+/// Serialize `body` and tag it with a content hash, answering `304 Not Modified` when the
+/// client already holds that version.
+fn json_with_etag<T: Serialize>(body: &T, if_none_match: Option<&str>) -> Response<Body> {
+    let body = serde_json::to_vec(body).expect("display matches always serialize");
+    let etag = format!("\"{}\"", hex::encode(&Sha256::digest(&body)[..16]));
+
+    let builder = Response::builder()
+        .header("etag", &etag)
+        .header("cache-control", "no-cache");
+
+    if if_none_match.is_some_and(|header| etag_matches(header, &etag)) {
+        return builder
+            .status(http::StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    builder
+        .status(http::StatusCode::OK)
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// `If-None-Match` may be `*` or a list of (possibly weak) tags.
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header.split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+fn unavailable(competition_division: &CompetitionDivisionPair) -> Response<Body> {
+    ApiError::new(
+        http::StatusCode::BAD_GATEWAY,
+        "matches_unavailable",
+        format!(
+            "No matches available for event {} division {}",
+            competition_division.competition_id, competition_division.division_id
+        ),
+    ).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matchSource::{FixtureClock, FixtureSource, Recording, Snapshot};
+    use crate::mockApns::{MockApns, MockApnsConfig};
+    use crate::tests::{subscribe, test_match, test_store_with_source, LIVE_ACTIVITY_TOKEN};
+    use warp::hyper::body::Bytes;
+
+    fn store(mock: &MockApns) -> StateStore {
+        test_store_with_source(mock, FixtureSource::new(vec![Recording {
+            competition_id: 1,
+            division_id: 1,
+            snapshots: vec![Snapshot {
+                offset_secs: 0,
+                matches: vec![
+                    test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (0, 0)),
+                    test_match(2, 2, ["4444E", "5555F"], ["6666G", "7777H"], (0, 0)),
+                ],
+            }],
+        }], FixtureClock::manual()))
+    }
+
+    async fn get(store: &StateStore, path: &str, if_none_match: Option<&str>) -> Response<Bytes> {
+        let mut request = warp::test::request().path(path);
+        if let Some(tag) = if_none_match {
+            request = request.header("if-none-match", tag);
+        }
+        request.reply(&crate::routes(store.clone())).await
+    }
+
+    #[tokio::test]
+    async fn only_polled_divisions_are_fetched_on_a_cache_miss() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = store(&mock);
+        let division = CompetitionDivisionPair::new(1, 1);
+
+        let response = get(&store, "/v1/events/1/divisions/1/matches", None).await;
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["code"], "division_not_polled");
+        assert!(store.matches.read().await.get(&division).is_none());
+
+        // once subscribed, the first request fetches the division and caches it
+        subscribe(&store, LIVE_ACTIVITY_TOKEN, "live_activity").await;
+        let response = get(&store, "/v1/events/1/divisions/1/matches", None).await;
+        assert_eq!(response.status(), http::StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        assert_eq!(store.matches.read().await.get(&division).map(Vec::len), Some(2));
+
+        let response = get(&store, "/v1/events/1/divisions/1/teams/5839a/schedule", None).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["name"], "Q 1");
+    }
+
+    #[tokio::test]
+    async fn unchanged_matches_are_not_modified() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let store = store(&mock);
+        subscribe(&store, LIVE_ACTIVITY_TOKEN, "live_activity").await;
+
+        let response = get(&store, "/v1/events/1/divisions/1/matches", None).await;
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+
+        let response = get(&store, "/v1/events/1/divisions/1/matches", Some(&etag)).await;
+        assert_eq!(response.status(), http::StatusCode::NOT_MODIFIED);
+        assert!(response.body().is_empty());
+
+        let weak_list = format!("\"stale\", W/{}", etag);
+        let response = get(&store, "/v1/events/1/divisions/1/matches", Some(&weak_list)).await;
+        assert_eq!(response.status(), http::StatusCode::NOT_MODIFIED);
+
+        let response = get(&store, "/v1/events/1/divisions/1/matches", Some("\"stale\"")).await;
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.headers()["etag"], etag.as_str());
+    }
+}
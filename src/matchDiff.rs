@@ -0,0 +1,209 @@
+use robotevents::schema::{AllianceColor, Match};
+use serde::Serialize;
+use crate::competitionAttributes::{has_score, DisplayMatch};
+
+/// Something that happened to one match between two polls of its division. Streams,
+/// webhooks, alerts and logs all work from these rather than comparing match lists.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MatchChange {
+    /// in the list for the first time, e.g. the next elimination round
+    MatchAdded {
+        #[serde(rename = "match")]
+        display_match: DisplayMatch,
+    },
+    /// gone from the list
+    MatchRemoved {
+        #[serde(rename = "match")]
+        display_match: DisplayMatch,
+    },
+    /// its scheduled time moved (or its start time changed once set)
+    ScheduleChanged {
+        #[serde(rename = "match")]
+        display_match: DisplayMatch,
+    },
+    /// it has a start time where it had none
+    MatchStarted {
+        #[serde(rename = "match")]
+        display_match: DisplayMatch,
+    },
+    /// it has a score where it had none
+    ScorePosted {
+        #[serde(rename = "match")]
+        display_match: DisplayMatch,
+    },
+    /// its score changed after being posted
+    ScoreCorrected {
+        match_id: i32,
+        #[serde(rename = "match")]
+        display_match: DisplayMatch,
+        previous: Box<DisplayMatch>,
+    },
+    /// a team was swapped onto or off one of its alliances
+    TeamsChanged {
+        #[serde(rename = "match")]
+        display_match: DisplayMatch,
+        previous: Box<DisplayMatch>,
+    },
+}
+
+impl MatchChange {
+    /// The `type` it serializes with, which is also the SSE event and webhook event name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MatchChange::MatchAdded { .. } => "match_added",
+            MatchChange::MatchRemoved { .. } => "match_removed",
+            MatchChange::ScheduleChanged { .. } => "schedule_changed",
+            MatchChange::MatchStarted { .. } => "match_started",
+            MatchChange::ScorePosted { .. } => "score_posted",
+            MatchChange::ScoreCorrected { .. } => "score_corrected",
+            MatchChange::TeamsChanged { .. } => "teams_changed",
+        }
+    }
+
+    /// The match as it now stands (as it last stood, if removed).
+    pub fn display_match(&self) -> &DisplayMatch {
+        match self {
+            MatchChange::MatchAdded { display_match }
+            | MatchChange::MatchRemoved { display_match }
+            | MatchChange::ScheduleChanged { display_match }
+            | MatchChange::MatchStarted { display_match }
+            | MatchChange::ScorePosted { display_match }
+            | MatchChange::ScoreCorrected { display_match, .. }
+            | MatchChange::TeamsChanged { display_match, .. } => display_match,
+        }
+    }
+}
+
+/// An alliance's team names, sorted so reordering alone isn't a substitution.
+fn alliance_teams(m: &Match, color: &AllianceColor) -> Vec<String> {
+    let mut teams: Vec<String> = m.alliances.iter()
+        .filter(|alliance| &alliance.color == color)
+        .flat_map(|alliance| alliance.teams.iter().map(|team| team.team.name.to_uppercase()))
+        .collect();
+    teams.sort();
+    teams
+}
+
+/// An alliance's score, looked up by color since RobotEvents doesn't promise an order.
+fn alliance_score(m: &Match, color: &AllianceColor) -> Option<i32> {
+    m.alliances.iter().find(|alliance| &alliance.color == color).map(|alliance| alliance.score)
+}
+
+/// Compare two match lists for the same division and describe what changed, match by match
+/// in the new list's order, then anything removed. A match can change in several ways at once
+/// (started and scored between polls, say) and gets an event for each.
+pub fn diff_matches(old_matches: &[Match], new_matches: &[Match]) -> Vec<MatchChange> {
+    let mut changes = Vec::new();
+
+    for new_match in new_matches {
+        let display_match = DisplayMatch::from(new_match);
+
+        let Some(old_match) = old_matches.iter().find(|old| old.id == new_match.id) else {
+            let scored = has_score(new_match);
+            changes.push(MatchChange::MatchAdded { display_match: display_match.clone() });
+            if scored {
+                changes.push(MatchChange::ScorePosted { display_match });
+            }
+            continue;
+        };
+        let previous = || Box::new(DisplayMatch::from(old_match));
+
+        if [AllianceColor::Red, AllianceColor::Blue].iter().any(|color| alliance_teams(old_match, color) != alliance_teams(new_match, color)) {
+            changes.push(MatchChange::TeamsChanged { display_match: display_match.clone(), previous: previous() });
+        }
+
+        let started = old_match.started.is_none() && new_match.started.is_some();
+        if started {
+            changes.push(MatchChange::MatchStarted { display_match: display_match.clone() });
+        }
+        // one event however many of the times moved
+        if (!started && old_match.started != new_match.started) || old_match.scheduled != new_match.scheduled {
+            changes.push(MatchChange::ScheduleChanged { display_match: display_match.clone() });
+        }
+
+        let scores_changed = [AllianceColor::Red, AllianceColor::Blue].iter().any(|color| alliance_score(old_match, color) != alliance_score(new_match, color));
+        if scores_changed {
+            changes.push(if has_score(old_match) {
+                MatchChange::ScoreCorrected {
+                    match_id: new_match.id,
+                    display_match: DisplayMatch { corrected: true, ..display_match },
+                    previous: previous(),
+                }
+            } else {
+                MatchChange::ScorePosted { display_match }
+            });
+        }
+    }
+
+    for old_match in old_matches.iter().filter(|old| !new_matches.iter().any(|new| new.id == old.id)) {
+        changes.push(MatchChange::MatchRemoved { display_match: DisplayMatch::from(old_match) });
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_match;
+
+    fn names(changes: &[MatchChange]) -> Vec<(&'static str, &str)> {
+        changes.iter().map(|change| (change.name(), change.display_match().name.as_str())).collect()
+    }
+
+    #[test]
+    fn describes_each_kind_of_change() {
+        let unscored = test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (0, 0));
+        let mut started = unscored.clone();
+        started.started = Some("2025-03-08T09:02:00-06:00".to_string());
+        let mut scored = started.clone();
+        scored.alliances = test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (80, 95)).alliances;
+        let removed = test_match(2, 2, ["5839A", "2222C"], ["1234B", "3333D"], (0, 0));
+
+        let unscored = [unscored];
+        assert!(diff_matches(&unscored, &unscored).is_empty());
+        assert_eq!(names(&diff_matches(&[], &unscored)), [("match_added", "Q 1")]);
+        assert_eq!(
+            names(&diff_matches(&[unscored[0].clone(), removed], &[scored.clone()])),
+            [("match_started", "Q 1"), ("score_posted", "Q 1"), ("match_removed", "Q 2")],
+        );
+
+        // a referee fixes the score, and a team is substituted on the red alliance
+        let mut corrected = scored.clone();
+        corrected.alliances = test_match(1, 1, ["5839A", "9999Z"], ["2222C", "3333D"], (85, 95)).alliances;
+        let changes = diff_matches(&[scored], &[corrected]);
+        assert_eq!(names(&changes), [("teams_changed", "Q 1"), ("score_corrected", "Q 1")]);
+        let MatchChange::ScoreCorrected { match_id, display_match, previous } = &changes[1] else { unreachable!() };
+        assert_eq!((previous.red_alliance.score, display_match.red_alliance.score), (Some(80), Some(85)));
+        assert_eq!(*match_id, 1);
+        assert!(display_match.corrected && !previous.corrected);
+
+        let json = serde_json::to_value(&changes[1]).unwrap();
+        assert_eq!(json["type"], "score_corrected");
+        assert_eq!(json["match"]["corrected"], true);
+        assert_eq!(json["previous"]["redAlliance"]["score"], 80);
+
+        // the field reset moves both the start and the rest of the schedule
+        let mut rescheduled = started.clone();
+        rescheduled.started = Some("2025-03-08T09:05:00-06:00".to_string());
+        rescheduled.scheduled = Some("2025-03-08T09:10:00-06:00".to_string());
+        assert_eq!(names(&diff_matches(&[started], &[rescheduled])), [("schedule_changed", "Q 1")]);
+    }
+
+    #[test]
+    fn alliance_order_is_not_a_change() {
+        let scored = test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (80, 95));
+        let mut reordered = scored.clone();
+        reordered.alliances.reverse();
+        assert!(diff_matches(std::slice::from_ref(&scored), std::slice::from_ref(&reordered)).is_empty());
+
+        // a real correction still shows up, with each color's own score
+        reordered.alliances = test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (85, 95)).alliances;
+        reordered.alliances.reverse();
+        let changes = diff_matches(&[scored], &[reordered]);
+        assert_eq!(names(&changes), [("score_corrected", "Q 1")]);
+        let MatchChange::ScoreCorrected { display_match, previous, .. } = &changes[0] else { unreachable!() };
+        assert_eq!((previous.red_alliance.score, display_match.red_alliance.score), (Some(80), Some(85)));
+    }
+}
@@ -0,0 +1,444 @@
+use async_trait::async_trait;
+use robotevents::query::{DivisionMatchesQuery, PaginatedQuery, SeasonsQuery};
+use robotevents::schema::{Division, IdInfo, Match, PaginatedResponse};
+use robotevents::RobotEvents;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+#[cfg(test)]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(test)]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::metrics::METRICS;
+use crate::CompetitionDivisionPair;
+
+pub type MatchSourceError = Box<dyn Error + Send + Sync>;
+
+/// Where `StateStore` gets match lists from.
+#[async_trait]
+pub trait MatchSource: Send + Sync + Debug {
+    async fn division_matches(&self, competition_division: &CompetitionDivisionPair) -> Result<Vec<Match>, MatchSourceError>;
+
+    /// Whether a division is worth polling at all. Sources that can't tell accept everything.
+    async fn check_division(&self, _competition_division: &CompetitionDivisionPair) -> Result<DivisionCheck, MatchSourceError> {
+        Ok(DivisionCheck::Valid)
+    }
+}
+
+/// What RobotEvents says about a division someone wants to subscribe to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DivisionCheck {
+    Valid,
+    UnknownEvent,
+    UnknownDivision,
+    /// the event belongs to a season that is no longer active
+    PastSeason { season: String },
+}
+
+/// The parts of a RobotEvents event needed to check a division.
+#[derive(Deserialize, Debug)]
+struct EventSummary {
+    season: IdInfo,
+    divisions: Vec<Division>,
+}
+
+/// The live RobotEvents API.
+#[derive(Debug)]
+pub struct RobotEventsSource {
+    client: RobotEvents,
+}
+
+impl RobotEventsSource {
+    pub fn new(bearer_token: &str) -> Self {
+        RobotEventsSource {
+            client: RobotEvents::new(bearer_token),
+        }
+    }
+
+    /// GET a RobotEvents endpoint, counting the response status. `None` for a 404; the request
+    /// is made here rather than through the client's typed methods so an error page isn't
+    /// reported as bad JSON.
+    async fn get<T: DeserializeOwned>(&self, endpoint: String) -> Result<Option<T>, MatchSourceError> {
+        let response = self.client.request(endpoint).await;
+
+        let status = response.as_ref().map_or("error".to_string(), |response| response.status().as_u16().to_string());
+        METRICS.robotevents_requests.with_label_values(&[status]).inc();
+
+        let response = response?;
+        if response.status().as_u16() == 404 {
+            return Ok(None);
+        }
+
+        Ok(Some(response.error_for_status()?.json().await?))
+    }
+}
+
+#[async_trait]
+impl MatchSource for RobotEventsSource {
+    async fn division_matches(&self, competition_division: &CompetitionDivisionPair) -> Result<Vec<Match>, MatchSourceError> {
+        let matches: PaginatedResponse<Match> = self.get(format!(
+            "/events/{}/divisions/{}/matches{}",
+            competition_division.competition_id,
+            competition_division.division_id,
+            DivisionMatchesQuery::new().per_page(250),
+        )).await?.ok_or_else(|| format!("RobotEvents has no division {:?}", competition_division))?;
+
+        Ok(matches.data)
+    }
+
+    async fn check_division(&self, competition_division: &CompetitionDivisionPair) -> Result<DivisionCheck, MatchSourceError> {
+        let Some(event) = self.get::<EventSummary>(format!("/events/{}", competition_division.competition_id)).await? else {
+            return Ok(DivisionCheck::UnknownEvent);
+        };
+        if !event.divisions.iter().any(|division| division.id == competition_division.division_id) {
+            return Ok(DivisionCheck::UnknownDivision);
+        }
+
+        let active: PaginatedResponse<IdInfo> = self.get(format!("/seasons{}", SeasonsQuery::new().active(true)))
+            .await?
+            .ok_or("RobotEvents has no seasons endpoint")?;
+        if !active.data.iter().any(|season| season.id == event.season.id) {
+            return Ok(DivisionCheck::PastSeason { season: event.season.name });
+        }
+
+        Ok(DivisionCheck::Valid)
+    }
+}
+
+/// One division's match list as it looked `offset_secs` into a recording.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub offset_secs: u64,
+    pub matches: Vec<Match>,
+}
+
+/// Everything recorded for one division, stored as `{competition_id}-{division_id}.json`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Recording {
+    pub competition_id: i32,
+    pub division_id: i32,
+    pub snapshots: Vec<Snapshot>,
+}
+
+impl Recording {
+    fn file_name(competition_division: &CompetitionDivisionPair) -> String {
+        format!("{}-{}.json", competition_division.competition_id, competition_division.division_id)
+    }
+}
+
+/// How far into a recording a `FixtureSource` is.
+#[derive(Debug, Clone)]
+pub enum FixtureClock {
+    /// Wall-clock time since the source was created, multiplied by `speed`.
+    Realtime { started: Instant, speed: f64 },
+    /// Only moves when `advance` is called, for deterministic tests.
+    #[cfg(test)]
+    Manual(Arc<AtomicU64>),
+}
+
+impl FixtureClock {
+    pub fn realtime(speed: f64) -> Self {
+        FixtureClock::Realtime { started: Instant::now(), speed }
+    }
+
+    #[cfg(test)]
+    pub fn manual() -> Self {
+        FixtureClock::Manual(Arc::new(AtomicU64::new(0)))
+    }
+
+    pub fn elapsed_secs(&self) -> u64 {
+        match self {
+            FixtureClock::Realtime { started, speed } => (started.elapsed().as_secs_f64() * speed) as u64,
+            #[cfg(test)]
+            FixtureClock::Manual(offset) => offset.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Seconds of recording per wall-clock second; manual clocks count as real time.
+    pub fn speed(&self) -> f64 {
+        match self {
+            FixtureClock::Realtime { speed, .. } => *speed,
+            #[cfg(test)]
+            FixtureClock::Manual(_) => 1.0,
+        }
+    }
+
+    /// Move a manual clock forward. Realtime clocks ignore this.
+    #[cfg(test)]
+    pub fn advance(&self, secs: u64) {
+        if let FixtureClock::Manual(offset) = self {
+            offset.fetch_add(secs, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Replays recorded snapshots: each request gets the latest snapshot at or before the
+/// clock's current offset.
+#[derive(Debug)]
+pub struct FixtureSource {
+    recordings: HashMap<CompetitionDivisionPair, Vec<Snapshot>>,
+    clock: FixtureClock,
+}
+
+impl FixtureSource {
+    pub fn new(recordings: Vec<Recording>, clock: FixtureClock) -> Self {
+        let recordings = recordings.into_iter()
+            .map(|recording| {
+                let mut snapshots = recording.snapshots;
+                snapshots.sort_by_key(|snapshot| snapshot.offset_secs);
+                (CompetitionDivisionPair::new(recording.competition_id, recording.division_id), snapshots)
+            })
+            .collect();
+
+        FixtureSource { recordings, clock }
+    }
+
+    /// Load every `*.json` recording in `dir`.
+    pub fn load_dir(dir: &Path, clock: FixtureClock) -> Result<Self, Box<dyn Error>> {
+        let mut recordings = Vec::new();
+
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                let recording: Recording = serde_json::from_slice(&std::fs::read(&path)?)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                recordings.push(recording);
+            }
+        }
+
+        tracing::info!(recordings = recordings.len(), dir = %dir.display(), "loaded match recordings");
+
+        Ok(Self::new(recordings, clock))
+    }
+}
+
+#[async_trait]
+impl MatchSource for FixtureSource {
+    async fn division_matches(&self, competition_division: &CompetitionDivisionPair) -> Result<Vec<Match>, MatchSourceError> {
+        let snapshots = self.recordings.get(competition_division)
+            .ok_or_else(|| format!("No recording for {:?}", competition_division))?;
+
+        let elapsed = self.clock.elapsed_secs();
+
+        Ok(snapshots.iter()
+            .take_while(|snapshot| snapshot.offset_secs <= elapsed)
+            .last()
+            .map(|snapshot| snapshot.matches.clone())
+            .unwrap_or_default())
+    }
+}
+
+/// Passes requests through to another source and appends every distinct result to a
+/// recording in `dir`, so live events can be replayed later with `FixtureSource`. A recording
+/// already in `dir` is carried on rather than replaced.
+#[derive(Debug)]
+pub struct RecordingSource<S: MatchSource> {
+    inner: S,
+    dir: PathBuf,
+    clock: FixtureClock,
+    /// held while a recording is written, so writes for a division land in order
+    recordings: tokio::sync::Mutex<HashMap<CompetitionDivisionPair, Recorded>>,
+}
+
+#[derive(Debug)]
+struct Recorded {
+    recording: Recording,
+    /// added to the clock so offsets continue from an earlier run's
+    offset_shift: u64,
+}
+
+impl<S: MatchSource> RecordingSource<S> {
+    /// Snapshot offsets are taken from `clock`, normally `FixtureClock::realtime(1.0)`.
+    pub fn new(inner: S, dir: &Path, clock: FixtureClock) -> Result<Self, Box<dyn Error>> {
+        std::fs::create_dir_all(dir)?;
+
+        Ok(RecordingSource {
+            inner,
+            dir: dir.to_path_buf(),
+            clock,
+            recordings: tokio::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    async fn record(&self, competition_division: &CompetitionDivisionPair, matches: &[Match]) -> Result<(), MatchSourceError> {
+        let path = self.dir.join(Recording::file_name(competition_division));
+        let mut recordings = self.recordings.lock().await;
+
+        if !recordings.contains_key(competition_division) {
+            let existing = tokio::task::spawn_blocking({
+                let path = path.clone();
+                move || read_recording(&path)
+            })
+            .await??;
+
+            // carry on from the last snapshot, leaving the gap since it was written
+            let offset_shift = existing.as_ref()
+                .and_then(|(recording, idle)| Some(recording.snapshots.last()?.offset_secs + idle.as_secs().max(1)))
+                .map_or(0, |next| next.saturating_sub(self.clock.elapsed_secs()));
+
+            recordings.insert(competition_division.clone(), Recorded {
+                recording: existing.map(|(recording, _)| recording).unwrap_or_else(|| Recording {
+                    competition_id: competition_division.competition_id,
+                    division_id: competition_division.division_id,
+                    snapshots: Vec::new(),
+                }),
+                offset_shift,
+            });
+        }
+        let recorded = recordings.get_mut(competition_division).expect("inserted above");
+
+        if recorded.recording.snapshots.last().is_some_and(|last| last.matches == matches) {
+            return Ok(());
+        }
+
+        recorded.recording.snapshots.push(Snapshot {
+            offset_secs: self.clock.elapsed_secs() + recorded.offset_shift,
+            matches: matches.to_vec(),
+        });
+
+        let contents = serde_json::to_vec(&recorded.recording)?;
+        tokio::task::spawn_blocking(move || write_recording(&path, &contents)).await??;
+
+        Ok(())
+    }
+}
+
+/// An earlier run's recording at `path`, if there is one, with how long ago it was last written.
+fn read_recording(path: &Path) -> Result<Option<(Recording, Duration)>, MatchSourceError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let recording: Recording = serde_json::from_slice(&std::fs::read(path)?)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let idle = std::fs::metadata(path)?.modified()?.elapsed().unwrap_or_default();
+
+    Ok(Some((recording, idle)))
+}
+
+/// Replace the file in one rename, so a crash part way through keeps the previous recording.
+fn write_recording(path: &Path, contents: &[u8]) -> Result<(), MatchSourceError> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    std::fs::write(&temporary, contents)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+#[async_trait]
+impl<S: MatchSource> MatchSource for RecordingSource<S> {
+    async fn division_matches(&self, competition_division: &CompetitionDivisionPair) -> Result<Vec<Match>, MatchSourceError> {
+        let matches = self.inner.division_matches(competition_division).await?;
+
+        if let Err(e) = self.record(competition_division, &matches).await {
+            tracing::error!(?competition_division, error = %e, "unable to record matches");
+        }
+
+        Ok(matches)
+    }
+
+    async fn check_division(&self, competition_division: &CompetitionDivisionPair) -> Result<DivisionCheck, MatchSourceError> {
+        self.inner.check_division(competition_division).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_match;
+
+    fn division() -> CompetitionDivisionPair {
+        CompetitionDivisionPair::new(1, 1)
+    }
+
+    fn unscored() -> Vec<Match> {
+        vec![test_match(1, 1, ["1A", "2A"], ["3A", "4A"], (0, 0))]
+    }
+
+    fn scored() -> Vec<Match> {
+        vec![test_match(1, 1, ["1A", "2A"], ["3A", "4A"], (10, 5))]
+    }
+
+    #[tokio::test]
+    async fn fixture_serves_latest_snapshot_for_clock() {
+        let clock = FixtureClock::manual();
+        let source = FixtureSource::new(vec![Recording {
+            competition_id: 1,
+            division_id: 1,
+            snapshots: vec![
+                Snapshot { offset_secs: 60, matches: scored() },
+                Snapshot { offset_secs: 0, matches: unscored() },
+            ],
+        }], clock.clone());
+
+        assert_eq!(source.division_matches(&division()).await.unwrap(), unscored());
+        clock.advance(59);
+        assert_eq!(source.division_matches(&division()).await.unwrap(), unscored());
+        clock.advance(1);
+        assert_eq!(source.division_matches(&division()).await.unwrap(), scored());
+
+        assert!(source.division_matches(&CompetitionDivisionPair::new(2, 1)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn recordings_replay_through_fixture_source() {
+        let dir = std::env::temp_dir().join(format!("echoscope-recording-{}", std::process::id()));
+        let live = FixtureClock::manual();
+        let recorder = RecordingSource::new(
+            FixtureSource::new(vec![Recording {
+                competition_id: 1,
+                division_id: 1,
+                snapshots: vec![
+                    Snapshot { offset_secs: 0, matches: unscored() },
+                    Snapshot { offset_secs: 30, matches: scored() },
+                ],
+            }], live.clone()),
+            &dir,
+            live.clone(),
+        ).unwrap();
+
+        recorder.division_matches(&division()).await.unwrap();
+        recorder.division_matches(&division()).await.unwrap();
+        live.advance(30);
+        recorder.division_matches(&division()).await.unwrap();
+
+        let replay_clock = FixtureClock::manual();
+        let replay = FixtureSource::load_dir(&dir, replay_clock.clone()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(replay.recordings[&division()].len(), 2);
+        assert_eq!(replay.division_matches(&division()).await.unwrap(), unscored());
+        replay_clock.advance(u64::MAX / 2);
+        assert_eq!(replay.division_matches(&division()).await.unwrap(), scored());
+    }
+
+    #[tokio::test]
+    async fn recording_carries_on_after_a_restart() {
+        let dir = std::env::temp_dir().join(format!("echoscope-recording-restart-{}", std::process::id()));
+        let recorder = |matches: Vec<Match>| RecordingSource::new(
+            FixtureSource::new(vec![Recording {
+                competition_id: 1,
+                division_id: 1,
+                snapshots: vec![Snapshot { offset_secs: 0, matches }],
+            }], FixtureClock::manual()),
+            &dir,
+            FixtureClock::manual(),
+        ).unwrap();
+
+        recorder(unscored()).division_matches(&division()).await.unwrap();
+        recorder(scored()).division_matches(&division()).await.unwrap();
+
+        let recording = read_recording(&dir.join(Recording::file_name(&division()))).unwrap().unwrap().0;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(recording.snapshots.len(), 2);
+        assert_eq!(recording.snapshots[0].matches, unscored());
+        assert_eq!(recording.snapshots[1].matches, scored());
+        assert!(recording.snapshots[1].offset_secs > recording.snapshots[0].offset_secs);
+    }
+}
@@ -0,0 +1,116 @@
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+use crate::{CompetitionDivisionPair, StateStore};
+
+/// Everything `GET /metrics` reports. Counters are bumped where things happen; the per-division
+/// gauges are filled in from the store when scraped.
+pub struct Metrics {
+    registry: Registry,
+    /// RobotEvents match list requests, by HTTP status or `error` if none came back
+    pub robotevents_requests: IntCounterVec,
+    /// fetching and pushing one division during a poll
+    pub poll_duration: HistogramVec,
+    /// APNs pushes, by push type, result and Apple's reason code for failures
+    pub apns_pushes: IntCounterVec,
+    /// provider tokens signed or fetched, by provider
+    pub token_refreshes: IntCounterVec,
+    /// subscriptions dropped by the sweeper
+    pub subscriptions_expired: IntCounter,
+    /// changes seen in polled match lists, by kind (`score_posted`, `score_corrected`, ...)
+    pub match_changes: IntCounterVec,
+    active_subscriptions: IntGaugeVec,
+    match_data_age: GaugeVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("echoscope".to_string()), None)
+            .expect("the metrics prefix is valid");
+        let division = ["competition_id", "division_id"];
+
+        let metrics = Metrics {
+            robotevents_requests: IntCounterVec::new(
+                Opts::new("robotevents_requests_total", "RobotEvents match list requests"),
+                &["status"],
+            ).unwrap(),
+            poll_duration: HistogramVec::new(
+                HistogramOpts::new("poll_duration_seconds", "Time to fetch and push one division during a poll")
+                    .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+                &division,
+            ).unwrap(),
+            apns_pushes: IntCounterVec::new(
+                Opts::new("apns_pushes_total", "Pushes sent to APNs"),
+                &["push_type", "result", "reason"],
+            ).unwrap(),
+            token_refreshes: IntCounterVec::new(
+                Opts::new("token_refreshes_total", "Provider tokens signed or fetched"),
+                &["provider"],
+            ).unwrap(),
+            subscriptions_expired: IntCounter::new(
+                "subscriptions_expired_total",
+                "Subscriptions dropped after their time-to-live",
+            ).unwrap(),
+            match_changes: IntCounterVec::new(
+                Opts::new("match_changes_total", "Changes seen between polls of a division's matches"),
+                &["change"],
+            ).unwrap(),
+            active_subscriptions: IntGaugeVec::new(
+                Opts::new("active_subscriptions", "Devices subscribed to a division"),
+                &division,
+            ).unwrap(),
+            match_data_age: GaugeVec::new(
+                Opts::new("match_data_age_seconds", "Time since a division's cached match list was fetched"),
+                &division,
+            ).unwrap(),
+            registry,
+        };
+
+        metrics.registry.register(Box::new(metrics.robotevents_requests.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.poll_duration.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.apns_pushes.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.token_refreshes.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.subscriptions_expired.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.match_changes.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.active_subscriptions.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.match_data_age.clone())).unwrap();
+
+        metrics
+    }
+}
+
+/// Label values for a division, in the order the per-division metrics declare them.
+pub fn division_labels(competition_division: &CompetitionDivisionPair) -> [String; 2] {
+    [
+        competition_division.competition_id.to_string(),
+        competition_division.division_id.to_string(),
+    ]
+}
+
+/// `GET /metrics` in the Prometheus text format.
+pub async fn metrics(state_store: StateStore) -> Result<impl warp::Reply, warp::Rejection> {
+    // rebuilt on every scrape so divisions nobody watches any more drop out
+    METRICS.active_subscriptions.reset();
+    for (competition_division, devices) in state_store.subscriptions.read().await.iter() {
+        METRICS.active_subscriptions
+            .with_label_values(&division_labels(competition_division))
+            .set(devices.len() as i64);
+    }
+
+    METRICS.match_data_age.reset();
+    for (competition_division, fetched) in state_store.fetched_at.read().await.iter() {
+        METRICS.match_data_age
+            .with_label_values(&division_labels(competition_division))
+            .set(fetched.elapsed().as_secs_f64());
+    }
+
+    let mut body = Vec::new();
+    TextEncoder::new()
+        .encode(&METRICS.registry.gather(), &mut body)
+        .expect("metrics encode as text");
+
+    Ok(warp::reply::with_header(body, "content-type", prometheus::TEXT_FORMAT))
+}
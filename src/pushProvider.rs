@@ -0,0 +1,193 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::error::Error;
+use crate::apnsClient::ApnsClient;
+use crate::competitionAttributes::CompetitionAttributesContentState;
+use crate::fcmClient::FcmClient;
+use crate::liveActivityApns::LiveActivityClient;
+
+pub type PushError = Box<dyn Error + Send + Sync>;
+
+/// The operating system a subscription's token belongs to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Platform {
+    #[default]
+    Ios,
+    Android,
+}
+
+impl Platform {
+    /// Reject tokens that can't be real before they reach the store: APNs tokens (device and
+    /// Live Activity) are hex-encoded bytes, FCM registration tokens URL-safe base64 with a `:`.
+    pub fn validate_token(&self, token: &str) -> Result<(), String> {
+        match self {
+            Platform::Ios => {
+                if !(64..=200).contains(&token.len()) || !token.len().is_multiple_of(2) || !token.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err("device_token must be 64 to 200 hex digits".to_string());
+                }
+            }
+            Platform::Android => {
+                let allowed = |b: u8| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b':');
+                if !(32..=4096).contains(&token.len()) || !token.bytes().all(allowed) {
+                    return Err("device_token must be an FCM registration token".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// For requests that don't say which platform a token is from.
+    pub fn validate_any_token(token: &str) -> Result<(), String> {
+        Platform::Ios.validate_token(token)
+            .or_else(|_| Platform::Android.validate_token(token))
+            .map_err(|_| "device_token is neither an APNs nor an FCM token".to_string())
+    }
+}
+
+/// Which kind of APNs token a device registered. Live Activity push tokens and regular
+/// device tokens are not interchangeable, so each is sent on its own channel.
+/// Android registrations always use their FCM token and ignore this.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenType {
+    #[default]
+    LiveActivity,
+    Notification,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PushAlert {
+    pub title: String,
+    pub body: String,
+}
+
+/// Everything a provider needs to deliver one update to one device.
+#[derive(Debug, Clone)]
+pub struct PushUpdate {
+    pub content_state: CompetitionAttributesContentState,
+    /// Results worth interrupting the user for. Providers without a visible channel ignore these.
+    pub alerts: Vec<PushAlert>,
+}
+
+#[async_trait]
+pub trait PushProvider: Send + Sync {
+    async fn push_update(&self, device_token: &str, update: &PushUpdate) -> Result<(), PushError>;
+}
+
+#[async_trait]
+impl PushProvider for LiveActivityClient {
+    async fn push_update(&self, device_token: &str, update: &PushUpdate) -> Result<(), PushError> {
+        let payload = json!({
+            "aps": {
+                "timestamp": chrono::Utc::now().timestamp(),
+                "event": "update",
+                "content-state": update.content_state
+            }
+        });
+
+        tracing::debug!("sending Live Activity update");
+        tracing::trace!(%payload);
+
+        self.send_live_activity_notification(device_token, &payload).await
+    }
+}
+
+#[async_trait]
+impl PushProvider for ApnsClient {
+    async fn push_update(&self, device_token: &str, update: &PushUpdate) -> Result<(), PushError> {
+        let data = json!({ "content-state": update.content_state });
+
+        if update.alerts.is_empty() {
+            tracing::debug!("sending background update");
+            tracing::trace!(%data);
+
+            return self.send_background(device_token, &data).await;
+        }
+
+        for PushAlert { title, body } in &update.alerts {
+            tracing::debug!(title, "sending alert");
+
+            self.send_alert(device_token, title, body, &data).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Both APNs channels for one app. The bundle ID decides the topic, the client's
+/// credentials decide which developer team signs the push.
+#[derive(Debug)]
+pub struct ApnsApp {
+    pub live_activity: LiveActivityClient,
+    pub notification: ApnsClient,
+}
+
+impl ApnsApp {
+    pub fn new(client: ApnsClient) -> Self {
+        ApnsApp {
+            live_activity: LiveActivityClient::from_apns(client.clone()),
+            notification: client,
+        }
+    }
+}
+
+/// The delivery channels this backend has credentials for, keyed by app.
+#[derive(Debug)]
+pub struct PushProviders {
+    default_app: String,
+    apps: HashMap<String, ApnsApp>,
+    pub fcm: Option<FcmClient>,
+}
+
+impl PushProviders {
+    /// `default` serves subscriptions that don't say which app they came from.
+    pub fn new(default: ApnsClient, fcm: Option<FcmClient>) -> Self {
+        let default_app = default.bundle_id().to_string();
+
+        PushProviders {
+            apps: HashMap::from([(default_app.clone(), ApnsApp::new(default))]),
+            default_app,
+            fcm,
+        }
+    }
+
+    /// Serve another app, keyed by the client's bundle ID.
+    pub fn with_app(mut self, client: ApnsClient) -> Self {
+        self.apps.insert(client.bundle_id().to_string(), ApnsApp::new(client));
+        self
+    }
+
+    /// The bundle ID a subscription's `app_id` refers to, or `None` if no such app is configured.
+    pub fn resolve_app(&self, app_id: Option<&str>) -> Option<&str> {
+        match app_id {
+            None => Some(&self.default_app),
+            Some(app_id) => self.apps.get_key_value(app_id).map(|(app_id, _)| app_id.as_str()),
+        }
+    }
+
+    /// The APNs client behind an app, for managing its credentials. Its signer is shared with
+    /// the app's Live Activity channel and with any app on the same credentials.
+    pub fn apns_client(&self, app_id: Option<&str>) -> Option<&ApnsClient> {
+        self.apps.get(self.resolve_app(app_id)?).map(|app| &app.notification)
+    }
+
+    /// Every app's APNs client, ordered by bundle ID.
+    pub fn apns_clients(&self) -> impl Iterator<Item = &ApnsClient> {
+        let mut apps: Vec<_> = self.apps.iter().collect();
+        apps.sort_by_key(|(app_id, _)| *app_id);
+
+        apps.into_iter().map(|(_, app)| &app.notification)
+    }
+
+    /// Pick the provider for a registration, or `None` if that channel isn't configured.
+    pub fn provider_for(&self, app_id: &str, platform: Platform, token_type: TokenType) -> Option<&dyn PushProvider> {
+        match (platform, token_type) {
+            (Platform::Ios, TokenType::LiveActivity) => self.apps.get(app_id).map(|app| &app.live_activity as &dyn PushProvider),
+            (Platform::Ios, TokenType::Notification) => self.apps.get(app_id).map(|app| &app.notification as &dyn PushProvider),
+            (Platform::Android, _) => self.fcm.as_ref().map(|fcm| fcm as &dyn PushProvider),
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::http;
+use warp::{Filter, Rejection};
+use crate::apiError::ApiError;
+use crate::StateStore;
+
+/// Buckets kept at most; past this, full (idle) ones are dropped, then the least recently used.
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+/// How long a client turned away for capacity should wait before trying again.
+const CAPACITY_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Token buckets keyed by client IP or device token: `capacity` requests, refilled evenly.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+    capacity: f64,
+    refill_per_sec: f64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self::per_period(per_minute, 60.0)
+    }
+
+    pub fn per_hour(per_hour: u32) -> Self {
+        Self::per_period(per_hour, 60.0 * 60.0)
+    }
+
+    fn per_period(requests: u32, period_secs: f64) -> Self {
+        RateLimiter {
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+            capacity: requests as f64,
+            refill_per_sec: requests as f64 / period_secs,
+        }
+    }
+
+    /// Spend one request for `key`, or say how long until it may try again.
+    pub fn check(&self, key: &str) -> Result<(), Throttled> {
+        let (capacity, refill_per_sec) = (self.capacity, self.refill_per_sec);
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_KEYS && !buckets.contains_key(key) {
+            buckets.retain(|_, bucket| bucket.tokens + bucket.updated.elapsed().as_secs_f64() * refill_per_sec < capacity);
+
+            // clients that keep their buckets drained mustn't be able to grow the map without bound
+            while buckets.len() >= MAX_TRACKED_KEYS {
+                let Some(oldest) = buckets.iter().min_by_key(|(_, bucket)| bucket.updated).map(|(key, _)| key.clone()) else { break };
+                buckets.remove(&oldest);
+            }
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * refill_per_sec).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(Throttled {
+                retry_after: Duration::from_secs_f64((1.0 - bucket.tokens) / refill_per_sec),
+            });
+        }
+
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// Too many requests from one client or for one device token.
+#[derive(Debug)]
+pub struct Throttled {
+    pub retry_after: Duration,
+}
+
+impl From<Throttled> for Rejection {
+    fn from(throttled: Throttled) -> Self {
+        ApiError::new(http::StatusCode::TOO_MANY_REQUESTS, "rate_limited", "too many requests")
+            .with_retry_after(throttled.retry_after)
+            .into()
+    }
+}
+
+/// Already polling `limits.max_divisions` divisions; nothing new can be added.
+#[derive(Debug)]
+pub struct AtCapacity;
+
+impl From<AtCapacity> for ApiError {
+    fn from(_: AtCapacity) -> Self {
+        ApiError::new(http::StatusCode::SERVICE_UNAVAILABLE, "at_capacity", "not accepting new divisions right now")
+            .with_retry_after(CAPACITY_RETRY_AFTER)
+    }
+}
+
+impl From<AtCapacity> for Rejection {
+    fn from(at_capacity: AtCapacity) -> Self {
+        ApiError::from(at_capacity).into()
+    }
+}
+
+/// Who a request is from: the configured proxy header (e.g. Fly's `Fly-Client-IP`), else the peer address.
+fn client_ip(store: StateStore) -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    warp::header::headers_cloned()
+        .and(warp::addr::remote())
+        .map(move |headers: http::HeaderMap, remote: Option<SocketAddr>| {
+            store.config.limits.client_ip_header.as_deref()
+                .and_then(|header| headers.get(header))
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_string())
+                .or_else(|| remote.map(|remote| remote.ip().to_string()))
+                .unwrap_or_else(|| "unknown".to_string())
+        })
+}
+
+/// Turn away a client IP that has used up its requests for the minute.
+pub fn per_ip(store: StateStore) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    limit_ip(store, |store| &store.ip_limiter)
+}
+
+/// Turn away a client IP that has registered its share of installs for the hour.
+pub fn installs_per_ip(store: StateStore) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    limit_ip(store, |store| &store.install_limiter)
+}
+
+fn limit_ip(store: StateStore, limiter: fn(&StateStore) -> &RateLimiter) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    client_ip(store.clone())
+        .and_then(move |ip: String| {
+            let result = limiter(&store).check(&ip).map_err(Rejection::from);
+            async move { result }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let limiter = RateLimiter::new(2);
+
+        assert!(limiter.check("1.2.3.4").is_ok());
+        assert!(limiter.check("1.2.3.4").is_ok());
+        let throttled = limiter.check("1.2.3.4").unwrap_err();
+        assert!(throttled.retry_after > Duration::from_secs(29) && throttled.retry_after <= Duration::from_secs(30));
+        assert!(limiter.check("5.6.7.8").is_ok());
+
+        // half a minute later one request has come back
+        limiter.buckets.lock().unwrap().get_mut("1.2.3.4").unwrap().updated -= Duration::from_secs(30);
+        assert!(limiter.check("1.2.3.4").is_ok());
+        assert!(limiter.check("1.2.3.4").is_err());
+    }
+
+    #[test]
+    fn tracked_keys_are_capped_even_when_none_are_idle() {
+        let limiter = RateLimiter::per_hour(1);
+        for i in 0..MAX_TRACKED_KEYS {
+            limiter.check(&format!("10.0.{}.{}", i / 256, i % 256)).unwrap();
+        }
+        limiter.buckets.lock().unwrap().get_mut("10.0.0.0").unwrap().updated -= Duration::from_secs(1);
+
+        // every bucket is drained, so the least recently used one makes room
+        assert!(limiter.check("1.2.3.4").is_ok());
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), MAX_TRACKED_KEYS);
+        assert!(!buckets.contains_key("10.0.0.0") && buckets.contains_key("1.2.3.4"));
+    }
+}
@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use crate::competitionAttributes::DisplayMatch;
+use crate::matchDiff::MatchChange;
+use crate::CompetitionDivisionPair;
+
+/// Scores referees have fixed after posting, per division, so displays can flag the matches
+/// and `/corrections` can say what changed and when. Kept as long as the division's matches are.
+#[derive(Debug, Clone, Default)]
+pub struct CorrectionLog {
+    divisions: Arc<Mutex<HashMap<CompetitionDivisionPair, Vec<ScoreCorrection>>>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScoreCorrection {
+    pub match_id: i32,
+    #[serde(rename = "match")]
+    pub name: String,
+    pub previous: Scores,
+    pub corrected: Scores,
+    pub corrected_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scores {
+    pub red: i32,
+    pub blue: i32,
+}
+
+impl From<&DisplayMatch> for Scores {
+    fn from(display_match: &DisplayMatch) -> Self {
+        Scores {
+            red: display_match.red_alliance.score.unwrap_or(0),
+            blue: display_match.blue_alliance.score.unwrap_or(0),
+        }
+    }
+}
+
+impl CorrectionLog {
+    /// Note every score correction among a poll's `changes`, as of `now`.
+    pub fn record(&self, competition_division: &CompetitionDivisionPair, changes: &[MatchChange], now: DateTime<Utc>) {
+        let corrections: Vec<ScoreCorrection> = changes.iter()
+            .filter_map(|change| match change {
+                MatchChange::ScoreCorrected { match_id, display_match, previous } => Some(ScoreCorrection {
+                    match_id: *match_id,
+                    name: display_match.name.clone(),
+                    previous: Scores::from(previous.as_ref()),
+                    corrected: Scores::from(display_match),
+                    corrected_at: now,
+                }),
+                _ => None,
+            })
+            .collect();
+        if corrections.is_empty() {
+            return;
+        }
+
+        self.divisions.lock().unwrap()
+            .entry(competition_division.clone())
+            .or_default()
+            .extend(corrections);
+    }
+
+    /// Every correction in a division, oldest first.
+    pub fn corrections(&self, competition_division: &CompetitionDivisionPair) -> Vec<ScoreCorrection> {
+        self.divisions.lock().unwrap().get(competition_division).cloned().unwrap_or_default()
+    }
+
+    /// IDs of the division's matches that have had a score corrected.
+    pub fn corrected_matches(&self, competition_division: &CompetitionDivisionPair) -> HashSet<i32> {
+        self.divisions.lock().unwrap().get(competition_division)
+            .map(|corrections| corrections.iter().map(|correction| correction.match_id).collect())
+            .unwrap_or_default()
+    }
+
+    /// Every division's corrections, for saving across a restart.
+    pub fn snapshot(&self) -> HashMap<CompetitionDivisionPair, Vec<ScoreCorrection>> {
+        self.divisions.lock().unwrap().clone()
+    }
+
+    /// Put back corrections saved by `snapshot`.
+    pub fn restore(&self, divisions: HashMap<CompetitionDivisionPair, Vec<ScoreCorrection>>) {
+        self.divisions.lock().unwrap().extend(divisions);
+    }
+
+    /// Forget divisions no longer polled.
+    pub fn retain(&self, polled: &[CompetitionDivisionPair]) {
+        self.divisions.lock().unwrap().retain(|competition_division, _| polled.contains(competition_division));
+    }
+}
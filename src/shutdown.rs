@@ -0,0 +1,81 @@
+use std::time::Duration;
+use tokio::sync::watch;
+use warp::http;
+use warp::{Filter, Rejection};
+use crate::apiError::ApiError;
+use crate::StateStore;
+
+/// How long a client turned away during a deploy should wait; the new machine is up by then.
+const DRAINING_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Set once a shutdown signal arrives. The server stops taking changes, the poll loop finishes
+/// its cycle and the background tasks return, so `serve` can flush state and exit.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    draining: watch::Sender<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown {
+            draining: watch::channel(false).0,
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn begin(&self) {
+        self.draining.send_replace(true);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    /// Resolves once `begin` has been called (straight away if it already has).
+    pub async fn requested(&self) {
+        let mut draining = self.draining.subscribe();
+        // the sender lives as long as `self`, so this can't fail
+        let _ = draining.wait_for(|draining| *draining).await;
+    }
+}
+
+/// SIGTERM (what Fly sends on deploy) or Ctrl-C.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => tracing::info!("received SIGTERM"),
+                    _ = tokio::signal::ctrl_c() => tracing::info!("received SIGINT"),
+                }
+                return;
+            }
+            Err(e) => tracing::warn!(error = %e, "unable to listen for SIGTERM; only Ctrl-C will shut down cleanly"),
+        }
+    }
+
+    if tokio::signal::ctrl_c().await.is_ok() {
+        tracing::info!("received SIGINT");
+    }
+}
+
+/// Turn away subscription, install and webhook changes once shutdown has begun; they'd only
+/// be lost, or missed by the snapshot.
+pub fn accepting_changes(store: StateStore) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let result: Result<(), Rejection> = if store.shutdown.is_draining() {
+                Err(ApiError::new(http::StatusCode::SERVICE_UNAVAILABLE, "shutting_down", "shutting down; try again shortly")
+                    .with_retry_after(DRAINING_RETRY_AFTER)
+                    .into())
+            } else {
+                Ok(())
+            };
+            async move { result }
+        })
+        .untuple_one()
+}
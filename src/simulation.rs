@@ -0,0 +1,388 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use robotevents::schema::Match;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::time::{sleep_until, Instant};
+use tracing::Instrument;
+use crate::matchSource::{FixtureClock, MatchSource, MatchSourceError, Recording};
+use crate::mockApns::{MockApns, MockApnsConfig};
+use crate::pushProvider::{Platform, PushProviders, TokenType};
+use crate::config::Config;
+use crate::{push_providers_from_config, routes, CompetitionDivisionPair, DeviceSubscription, StateStore};
+
+/// How long after a match starts its score shows up.
+const MATCH_DURATION_SECS: i64 = 180;
+/// Spacing for matches that have neither a start nor a scheduled time.
+const UNTIMED_GAP_SECS: i64 = 420;
+/// How long before the first match the replay begins.
+const LEAD_SECS: i64 = 600;
+/// Elimination matches only exist on RobotEvents once the bracket is generated.
+const ELIMINATION_REVEAL_SECS: i64 = 1200;
+
+#[derive(clap::Args, Debug)]
+pub struct SimulateArgs {
+    /// Completed match list: a JSON array of matches, a RobotEvents page, or a recording
+    #[arg(long)]
+    event: PathBuf,
+    /// Replay speed, e.g. `60x` plays an hour of event in a minute
+    #[arg(long, default_value = "60x", value_parser = parse_speed)]
+    speed: f64,
+    /// Device tokens to push to; repeat for several devices
+    #[arg(long = "device-token")]
+    device_tokens: Vec<String>,
+    /// Team the devices follow
+    #[arg(long, default_value = "")]
+    team: String,
+    #[arg(long, value_enum, default_value = "live-activity")]
+    token_type: TokenType,
+    #[arg(long, value_enum, default_value = "ios")]
+    platform: Platform,
+    /// Bundle ID of the app the devices belong to; the configured default if omitted
+    #[arg(long)]
+    app_id: Option<String>,
+    /// Push to an in-process mock APNs instead of Apple
+    #[arg(long)]
+    mock_apns: bool,
+    /// Seconds between polls of the simulated event
+    #[arg(long, default_value_t = 5)]
+    poll_interval: u64,
+    /// Also serve the HTTP API (streams, match API) on this port while replaying
+    #[arg(long)]
+    port: Option<u16>,
+}
+
+pub fn parse_speed(speed: &str) -> Result<f64, String> {
+    let speed: f64 = speed.trim_end_matches(['x', 'X']).parse().map_err(|_| format!("invalid speed `{}`", speed))?;
+    if speed <= 0.0 {
+        return Err("speed must be positive".to_string());
+    }
+    Ok(speed)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EventFile {
+    Matches(Vec<Match>),
+    Page { data: Vec<Match> },
+    Recording(Recording),
+}
+
+#[derive(Debug)]
+struct TimedMatch {
+    completed: Match,
+    /// seconds after the replay origin that the match starts
+    start_offset: i64,
+    /// seconds after the replay origin it was originally scheduled for, if it was
+    scheduled_offset: Option<i64>,
+}
+
+/// Serves a completed event as though it were happening now: matches start and get
+/// scored as the clock passes them, and their timestamps are compressed by the replay
+/// speed so countdowns on device line up with what the backend sends.
+#[derive(Debug)]
+pub struct SimulatedSource {
+    timelines: HashMap<CompetitionDivisionPair, Vec<TimedMatch>>,
+    real_start: DateTime<Utc>,
+    speed: f64,
+    clock: FixtureClock,
+}
+
+impl SimulatedSource {
+    /// `matches` may span several divisions; they all share one clock.
+    pub fn new(matches: Vec<Match>, clock: FixtureClock) -> Result<Self, Box<dyn Error>> {
+        if matches.is_empty() {
+            return Err("event has no matches".into());
+        }
+
+        let parse = |time: &Option<String>| time.as_ref()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.with_timezone(&Utc));
+
+        let origin = matches.iter()
+            .filter_map(|m| parse(&m.started).or_else(|| parse(&m.scheduled)))
+            .min()
+            .unwrap_or_else(Utc::now) - Duration::seconds(LEAD_SECS);
+
+        let mut divisions: HashMap<CompetitionDivisionPair, Vec<_>> = HashMap::new();
+        for m in matches {
+            let start = parse(&m.started).or_else(|| parse(&m.scheduled));
+            divisions.entry(CompetitionDivisionPair::new(m.event.id, m.division.id))
+                .or_default()
+                .push((m, start));
+        }
+
+        let timelines = divisions.into_iter()
+            .map(|(division, mut timed)| {
+                timed.sort_by_key(|(m, start)| (start.is_none(), *start, round_order(m.round), m.instance, m.matchnum));
+
+                let mut previous = LEAD_SECS - UNTIMED_GAP_SECS;
+                let timeline = timed.into_iter()
+                    .map(|(m, start)| {
+                        let start_offset = start.map_or(previous + UNTIMED_GAP_SECS, |start| (start - origin).num_seconds());
+                        previous = start_offset;
+
+                        TimedMatch {
+                            scheduled_offset: parse(&m.scheduled).map(|scheduled| (scheduled - origin).num_seconds()),
+                            completed: m,
+                            start_offset,
+                        }
+                    })
+                    .collect();
+
+                (division, timeline)
+            })
+            .collect();
+
+        Ok(SimulatedSource {
+            timelines,
+            real_start: Utc::now(),
+            speed: clock.speed(),
+            clock,
+        })
+    }
+
+    pub fn load(path: &Path, clock: FixtureClock) -> Result<Self, Box<dyn Error>> {
+        let matches = match serde_json::from_slice(&std::fs::read(path)?)? {
+            EventFile::Matches(matches) | EventFile::Page { data: matches } => matches,
+            EventFile::Recording(recording) => recording.snapshots.into_iter()
+                .max_by_key(|snapshot| snapshot.offset_secs)
+                .map(|snapshot| snapshot.matches)
+                .unwrap_or_default(),
+        };
+
+        Self::new(matches, clock)
+    }
+
+    pub fn divisions(&self) -> Vec<CompetitionDivisionPair> {
+        self.timelines.keys().cloned().collect()
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.timelines.values().map(Vec::len).sum()
+    }
+
+    /// The division `team` plays in, if it plays in any.
+    fn division_for_team(&self, team: &str) -> Option<CompetitionDivisionPair> {
+        let team = team.to_uppercase();
+        self.timelines.iter()
+            .find(|(_, timeline)| timeline.iter().any(|timed| timed.completed.alliances.iter()
+                .flat_map(|alliance| alliance.teams.iter())
+                .any(|alliance_team| alliance_team.team.name.to_uppercase() == team)))
+            .map(|(division, _)| division.clone())
+    }
+
+    /// True once every match has been scored.
+    pub fn finished(&self) -> bool {
+        let now = self.clock.elapsed_secs() as i64;
+        self.timelines.values()
+            .flatten()
+            .all(|timed| now >= timed.start_offset + MATCH_DURATION_SECS)
+    }
+
+    /// Real-world timestamp for a point in the replay, compressed by the replay speed.
+    fn display_time(&self, offset: i64) -> String {
+        let real_offset = (offset as f64 / self.speed) as i64;
+        (self.real_start + Duration::seconds(real_offset)).to_rfc3339()
+    }
+
+    /// A division's match list as RobotEvents would have served it `now` seconds into the event.
+    fn view(&self, division: &CompetitionDivisionPair, now: i64) -> Option<Vec<Match>> {
+        let timeline = self.timelines.get(division)?;
+
+        Some(timeline.iter()
+            .filter(|timed| timed.completed.round <= 2 || now >= timed.start_offset - ELIMINATION_REVEAL_SECS)
+            .map(|timed| {
+                let mut m = timed.completed.clone();
+                m.scheduled = timed.scheduled_offset.map(|offset| self.display_time(offset));
+
+                if now < timed.start_offset {
+                    m.started = None;
+                }
+                if now < timed.start_offset + MATCH_DURATION_SECS {
+                    m.scored = false;
+                    for alliance in m.alliances.iter_mut() {
+                        alliance.score = 0;
+                    }
+                }
+                if now >= timed.start_offset {
+                    m.started = Some(self.display_time(timed.start_offset));
+                }
+
+                m
+            })
+            .collect())
+    }
+}
+
+/// Same ordering `CompetitionAttributesContentState` uses: round of 16 sits between
+/// qualifications and quarterfinals.
+fn round_order(round: i32) -> i32 {
+    if round == 6 { 25 } else { round * 10 }
+}
+
+#[async_trait]
+impl MatchSource for SimulatedSource {
+    async fn division_matches(&self, competition_division: &CompetitionDivisionPair) -> Result<Vec<Match>, MatchSourceError> {
+        self.view(competition_division, self.clock.elapsed_secs() as i64)
+            .ok_or_else(|| format!("No simulated division {:?}", competition_division).into())
+    }
+}
+
+/// Push providers that deliver every configured app's pushes to `mock`.
+pub fn mock_push_providers(mock: &MockApns, config: &Config) -> PushProviders {
+    config.apps().into_iter()
+        .skip(1)
+        .fold(PushProviders::new(mock.client_for(&config.bundle_id), None), |providers, (bundle_id, _)| {
+            providers.with_app(mock.client_for(bundle_id))
+        })
+}
+
+/// Poll every simulated division through `update_all_subscriptions` until the last match
+/// is scored. Returns when each poll cycle started and how long it took.
+pub async fn replay(store: &StateStore, source: &SimulatedSource, poll_interval: std::time::Duration) -> Vec<(std::time::Instant, std::time::Duration)> {
+    // keep every division polled even when only stream clients are watching
+    for division in source.divisions() {
+        store.watch_division(&division);
+    }
+
+    let mut cycles = Vec::new();
+    loop {
+        let start_time = Instant::now();
+        let finished = source.finished();
+
+        store.update_all_subscriptions()
+            .instrument(tracing::info_span!("poll_cycle", cycle = cycles.len() + 1))
+            .await;
+        cycles.push((start_time.into_std(), start_time.elapsed()));
+
+        if finished {
+            return cycles;
+        }
+
+        sleep_until(start_time + poll_interval).await;
+    }
+}
+
+/// `EchoScopeBackend simulate`: replay an event through the normal poll and push path.
+pub async fn run(args: SimulateArgs, config_path: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let config = Config::load_with(config_path.as_deref(), |config| {
+        if args.mock_apns {
+            config.use_mock_apns();
+        }
+    })?;
+    crate::logging::init(&config.logging);
+
+    let source = Arc::new(SimulatedSource::load(&args.event, FixtureClock::realtime(args.speed))?);
+    let division = source.division_for_team(&args.team)
+        .or_else(|| source.divisions().into_iter().next())
+        .ok_or("event has no divisions")?;
+
+    let mock = args.mock_apns.then(|| MockApns::start(MockApnsConfig {
+        bundle_ids: config.bundle_ids(),
+        ..MockApnsConfig::default()
+    }));
+    let push_providers = match &mock {
+        Some(mock) => mock_push_providers(mock, &config),
+        None => push_providers_from_config(&config)?,
+    };
+
+    let store = StateStore::with_clients(config, push_providers, source.clone());
+
+    for device_token in &args.device_tokens {
+        store.add_subscription_from_device(DeviceSubscription {
+            competition_id: division.competition_id,
+            division_id: division.division_id,
+            device_token: device_token.clone(),
+            watch_team: args.team.clone(),
+            token_type: args.token_type,
+            platform: args.platform,
+            app_id: args.app_id.clone(),
+            ttl_secs: None,
+            install_id: None,
+        }).await?;
+    }
+
+    if let Some(port) = args.port {
+        tokio::spawn(warp::serve(routes(store.clone())).run(([0, 0, 0, 0], port)));
+    }
+
+    println!(
+        "Simulating {} divisions ({} matches) at {}x",
+        source.divisions().len(),
+        source.match_count(),
+        args.speed
+    );
+
+    replay(&store, &source, std::time::Duration::from_secs(args.poll_interval)).await;
+
+    println!("Simulation finished");
+    if let Some(mock) = mock {
+        println!("Mock APNs accepted {} pushes", mock.deliveries().len());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_match;
+
+    fn at(m: Match, time: &str) -> Match {
+        Match {
+            scheduled: Some(time.to_string()),
+            started: Some(time.to_string()),
+            ..m
+        }
+    }
+
+    #[test]
+    fn reveals_starts_and_scores_progressively() {
+        let clock = FixtureClock::manual();
+        let source = SimulatedSource::new(vec![
+            at(test_match(2, 2, ["1A", "2A"], ["3A", "4A"], (7, 9)), "2025-03-08T09:07:00Z"),
+            at(test_match(1, 1, ["1A", "5A"], ["6A", "7A"], (10, 5)), "2025-03-08T09:00:00Z"),
+        ], clock.clone()).unwrap();
+
+        let division = CompetitionDivisionPair::new(1, 1);
+        let view = |secs| source.view(&division, secs).unwrap();
+
+        // before the first match nothing has started
+        assert!(view(0).iter().all(|m| m.started.is_none() && !m.scored));
+
+        // first match under way but not scored
+        let during = view(LEAD_SECS + 60);
+        assert!(during[0].started.is_some());
+        assert_eq!(during[0].alliances[0].score, 0);
+
+        // first match scored, second not yet started
+        let after = view(LEAD_SECS + MATCH_DURATION_SECS);
+        assert_eq!(after[0].alliances[1].score, 10);
+        assert!(after[1].started.is_none());
+
+        assert!(!source.finished());
+        clock.advance((LEAD_SECS + 420 + MATCH_DURATION_SECS) as u64);
+        assert!(source.finished());
+    }
+
+    #[test]
+    fn hides_eliminations_until_bracket_is_generated() {
+        let final_match = Match {
+            round: 5,
+            ..at(test_match(3, 1, ["1A", "2A"], ["3A", "4A"], (7, 9)), "2025-03-08T15:00:00Z")
+        };
+        let source = SimulatedSource::new(vec![
+            at(test_match(1, 1, ["1A", "5A"], ["6A", "7A"], (10, 5)), "2025-03-08T09:00:00Z"),
+            final_match,
+        ], FixtureClock::manual()).unwrap();
+
+        let division = CompetitionDivisionPair::new(1, 1);
+        let final_offset = LEAD_SECS + 6 * 3600;
+        assert_eq!(source.view(&division, final_offset - ELIMINATION_REVEAL_SECS - 1).unwrap().len(), 1);
+        assert_eq!(source.view(&division, final_offset - ELIMINATION_REVEAL_SECS).unwrap().len(), 2);
+    }
+}
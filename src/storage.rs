@@ -0,0 +1,223 @@
+use chrono::{DateTime, Utc};
+use robotevents::schema::Match;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use crate::cluster::ClusterError;
+use crate::scoreCorrections::ScoreCorrection;
+use crate::webhooks::StoredWebhook;
+use crate::{CompetitionDivisionPair, StateStore, TeamTokenPair};
+
+/// What's written to `storage_path` on shutdown and read back on start. Each division's match
+/// list and score corrections are kept too, so the first poll after a restart is diffed
+/// against what was last pushed (announcing only what changed while the backend was down)
+/// and corrected matches stay flagged. A division with no saved list is polled from a
+/// baseline, which announces nothing.
+///
+/// Install and webhook secrets are saved as they are, since verifying and signing need them,
+/// so the file is a credential: it's only readable by the backend's user, and whatever holds
+/// it (the Fly volume, backups of it) needs guarding like any other secret store.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Snapshot {
+    saved_at: DateTime<Utc>,
+    subscriptions: Vec<StoredDivision>,
+    /// install ID to secret
+    installs: HashMap<String, String>,
+    /// install ID to when it last signed a request
+    #[serde(default)]
+    installs_last_used: HashMap<String, DateTime<Utc>>,
+    webhooks: Vec<StoredWebhook>,
+    #[serde(default)]
+    matches: Vec<StoredMatches>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredDivision {
+    #[serde(flatten)]
+    division: CompetitionDivisionPair,
+    devices: Vec<TeamTokenPair>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredMatches {
+    #[serde(flatten)]
+    division: CompetitionDivisionPair,
+    matches: Vec<Match>,
+    #[serde(default)]
+    corrections: Vec<ScoreCorrection>,
+}
+
+pub async fn snapshot(state_store: &StateStore) -> Snapshot {
+    let subscriptions = state_store.subscriptions.read().await.iter()
+        .map(|(division, devices)| StoredDivision {
+            division: division.clone(),
+            devices: devices.clone(),
+        })
+        .collect();
+
+    let mut corrections = state_store.corrections.snapshot();
+    let matches = state_store.matches.read().await.iter()
+        .map(|(division, matches)| StoredMatches {
+            division: division.clone(),
+            matches: matches.clone(),
+            corrections: corrections.remove(division).unwrap_or_default(),
+        })
+        .collect();
+
+    Snapshot {
+        saved_at: Utc::now(),
+        subscriptions,
+        installs: state_store.installs.snapshot().await,
+        installs_last_used: state_store.installs.last_used().await,
+        webhooks: state_store.webhooks.snapshot().await,
+        matches,
+    }
+}
+
+/// Put a snapshot's subscriptions, installs, webhooks and matches back, dropping subscriptions
+/// that expired while the backend was down. Fails if the cluster store can't take the
+/// subscriptions.
+pub async fn restore(state_store: &StateStore, snapshot: Snapshot) -> Result<(), ClusterError> {
+    {
+        let mut subscriptions = state_store.subscriptions.write().await;
+        state_store.cluster.modify_subscriptions(&mut subscriptions, move |subscriptions| {
+            for stored in snapshot.subscriptions {
+                let devices = subscriptions.entry(stored.division).or_default();
+                // another instance may have kept some of these going while this one was down
+                for pair in stored.devices {
+                    if !devices.iter().any(|existing| existing.device_token == pair.device_token) {
+                        devices.push(pair);
+                    }
+                }
+            }
+        })
+        .await?;
+    }
+    state_store.installs.restore(snapshot.installs, &snapshot.installs_last_used).await;
+    {
+        // not marked as fetched, so the first poll or read refreshes them
+        let mut matches = state_store.matches.write().await;
+        let mut corrections = HashMap::new();
+        for stored in snapshot.matches {
+            matches.insert(stored.division.clone(), stored.matches);
+            corrections.insert(stored.division, stored.corrections);
+        }
+        state_store.corrections.restore(corrections);
+    }
+    state_store.webhooks.restore(snapshot.webhooks).await;
+
+    state_store.remove_expired_subscriptions(Utc::now()).await;
+    Ok(())
+}
+
+/// Write the snapshot to `storage_path`, if one is configured. The file is replaced in one
+/// rename so a crash part way through leaves the previous snapshot intact.
+pub async fn save(state_store: &StateStore) -> Result<(), Box<dyn Error>> {
+    let Some(path) = &state_store.config.storage_path else {
+        tracing::warn!("no storage_path configured; subscriptions will not survive the restart");
+        return Ok(());
+    };
+
+    let snapshot = snapshot(state_store).await;
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    write_private(Path::new(&temporary), &serde_json::to_vec(&snapshot)?)
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(|e| format!("Unable to save state to {}: {}", path.display(), e))?;
+
+    tracing::info!(
+        path = %path.display(),
+        divisions = snapshot.subscriptions.len(),
+        installs = snapshot.installs.len(),
+        webhooks = snapshot.webhooks.len(),
+        matches = snapshot.matches.len(),
+        "saved state"
+    );
+    Ok(())
+}
+
+/// Write `contents` to `path`, readable and writable by this user alone: the snapshot holds
+/// install and webhook secrets.
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+    // set before anything is written, and on a file left over from an earlier save too
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(contents)
+}
+
+/// Read the snapshot at `storage_path` back in, if one is configured and has been written.
+pub async fn load(state_store: &StateStore) -> Result<(), Box<dyn Error>> {
+    let Some(path) = &state_store.config.storage_path else {
+        return Ok(());
+    };
+    if !path.exists() {
+        tracing::info!(path = %path.display(), "no saved state yet");
+        return Ok(());
+    }
+
+    let snapshot = read(path)?;
+    tracing::info!(path = %path.display(), saved_at = %snapshot.saved_at, "restoring saved state");
+    restore(state_store, snapshot).await
+        .map_err(|e| format!("Unable to restore saved subscriptions to the cluster store: {}", e))?;
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<Snapshot, Box<dyn Error>> {
+    let contents = fs::read(path).map_err(|e| format!("Unable to read saved state {}: {}", path.display(), e))?;
+    Ok(serde_json::from_slice(&contents).map_err(|e| format!("Saved state {} is corrupt: {}", path.display(), e))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::matchSource::{FixtureClock, FixtureSource};
+    use crate::mockApns::{MockApns, MockApnsConfig};
+    use crate::pushProvider::PushProviders;
+    use crate::tests::test_match;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn state_survives_a_restart() {
+        let mock = MockApns::start(MockApnsConfig::default());
+        let path = std::env::temp_dir().join(format!("echoscope-state-{}.json", crate::webhooks::random_hex(8)));
+        let store = || StateStore::with_clients(
+            Config { storage_path: Some(path.clone()), ..Config::default() },
+            PushProviders::new(mock.client(), None),
+            Arc::new(FixtureSource::new(Vec::new(), FixtureClock::manual())),
+        );
+
+        let before = store();
+        let division = CompetitionDivisionPair::new(1, 1);
+        crate::tests::subscribe(&before, crate::tests::LIVE_ACTIVITY_TOKEN, "live_activity").await;
+        before.apply_matches(&division, vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (80, 95))]).await;
+        before.apply_matches(&division, vec![test_match(1, 1, ["5839A", "1234B"], ["2222C", "3333D"], (100, 95))]).await;
+        save(&before).await.unwrap();
+        assert!(path.exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let after = store();
+        load(&after).await.unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(after.subscription_records(None, None).await, before.subscription_records(None, None).await);
+        assert_eq!(after.installs.snapshot().await, before.installs.snapshot().await);
+        assert_eq!(after.installs.snapshot().await.len(), 1);
+        assert_eq!(after.polled_divisions().await, vec![division.clone()]);
+        assert_eq!(after.matches.read().await[&division], before.matches.read().await[&division]);
+        assert_eq!(after.corrections.corrections(&division), before.corrections.corrections(&division));
+        assert_eq!(after.corrections.corrections(&division).len(), 1);
+    }
+}
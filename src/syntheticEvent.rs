@@ -0,0 +1,383 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use robotevents::schema::{Alliance, AllianceColor, AllianceTeam, IdInfo, Match};
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Time between consecutive matches on the schedule, across both fields.
+const MATCH_GAP_SECS: i64 = 240;
+/// Alliance selection between the last qualifier and the first elimination match.
+const ALLIANCE_SELECTION_SECS: i64 = 3600;
+
+/// Shape of a fake event. Everything is derived from `seed`, so the same spec always
+/// produces the same matches.
+#[derive(clap::Args, Debug, Clone)]
+pub struct EventSpec {
+    #[arg(long, default_value_t = 90001)]
+    pub competition_id: i32,
+    #[arg(long, default_value_t = 1)]
+    pub divisions: i32,
+    /// Teams in each division
+    #[arg(long, default_value_t = 64)]
+    pub teams: usize,
+    #[arg(long, default_value_t = 8)]
+    pub matches_per_team: usize,
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct GenerateArgs {
+    #[command(flatten)]
+    spec: EventSpec,
+    /// Where to write the match list; `simulate --event` reads it back
+    #[arg(long)]
+    out: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+struct Team {
+    info: IdInfo,
+    /// average points this team contributes to its alliance
+    strength: i32,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Record {
+    wins: u32,
+    ties: u32,
+    points: i32,
+}
+
+/// Builds one division, keeping the running schedule delay as it goes.
+struct DivisionBuilder<'a> {
+    spec: &'a EventSpec,
+    division: IdInfo,
+    rng: StdRng,
+    matches: Vec<Match>,
+    next_slot: DateTime<Utc>,
+    /// how far behind schedule the event is running
+    delay_secs: i64,
+}
+
+/// A completed event: qualifications, alliance selection and a full elimination bracket
+/// for every division, with scores and start times filled in.
+pub fn generate(spec: &EventSpec) -> Vec<Match> {
+    (1..=spec.divisions)
+        .flat_map(|division_id| {
+            let mut rng = StdRng::seed_from_u64(spec.seed.wrapping_add(division_id as u64));
+            let teams = generate_teams(&mut rng, spec.teams, division_id);
+
+            let mut builder = DivisionBuilder {
+                spec,
+                division: IdInfo {
+                    id: division_id,
+                    name: format!("Division {}", division_id),
+                    code: None,
+                },
+                rng,
+                matches: Vec::new(),
+                next_slot: Utc.with_ymd_and_hms(2025, 4, 23, 14, 0, 0).unwrap(),
+                delay_secs: 0,
+            };
+
+            let records = builder.qualifications(&teams);
+            builder.eliminations(&teams, &records);
+            builder.matches
+        })
+        .collect()
+}
+
+pub fn run_generate(args: GenerateArgs) -> Result<(), Box<dyn Error>> {
+    let matches = generate(&args.spec);
+    std::fs::write(&args.out, serde_json::to_vec_pretty(&matches)?)?;
+
+    println!("Wrote {} matches to {}", matches.len(), args.out.display());
+
+    Ok(())
+}
+
+fn generate_teams(rng: &mut StdRng, count: usize, division_id: i32) -> Vec<Team> {
+    let mut names = HashSet::new();
+
+    while names.len() < count {
+        let letter = (b'A' + rng.gen_range(0..6u8)) as char;
+        names.insert(format!("{}{}", rng.gen_range(1..=99999), letter));
+    }
+
+    let mut names: Vec<String> = names.into_iter().collect();
+    names.sort();
+
+    names.into_iter()
+        .enumerate()
+        .map(|(i, name)| Team {
+            info: IdInfo {
+                id: division_id * 100_000 + i as i32,
+                name,
+                code: None,
+            },
+            // sum of uniforms: roughly normal, mean 36
+            strength: (0..3).map(|_| rng.gen_range(0..=24)).sum(),
+        })
+        .collect()
+}
+
+impl DivisionBuilder<'_> {
+    fn qualifications(&mut self, teams: &[Team]) -> Vec<Record> {
+        let mut records = vec![Record::default(); teams.len()];
+        if teams.len() < 4 {
+            return records;
+        }
+
+        let match_count = (teams.len() * self.spec.matches_per_team).div_ceil(4);
+        let mut queue = VecDeque::new();
+
+        for matchnum in 1..=match_count as i32 {
+            // deal from repeated shuffles so play counts stay even, never seating a team twice
+            let mut seated: Vec<usize> = Vec::with_capacity(4);
+            while seated.len() < 4 {
+                if queue.is_empty() {
+                    let mut round: Vec<usize> = (0..teams.len()).collect();
+                    round.shuffle(&mut self.rng);
+                    queue.extend(round);
+                }
+
+                let position = queue.iter().position(|team| !seated.contains(team));
+                match position {
+                    Some(position) => seated.push(queue.remove(position).unwrap()),
+                    None => {
+                        let mut round: Vec<usize> = (0..teams.len()).collect();
+                        round.shuffle(&mut self.rng);
+                        queue.extend(round);
+                    }
+                }
+            }
+
+            let red = [seated[0], seated[1]];
+            let blue = [seated[2], seated[3]];
+            let (red_score, blue_score) = self.play(teams, &red, &blue);
+
+            for (alliance, score, opponent) in [(red, red_score, blue_score), (blue, blue_score, red_score)] {
+                for team in alliance {
+                    let record = &mut records[team];
+                    record.points += score;
+                    if score > opponent {
+                        record.wins += 1;
+                    } else if score == opponent {
+                        record.ties += 1;
+                    }
+                }
+            }
+
+            self.push_match(2, 1, matchnum, format!("Qualifier #{}", matchnum), teams, &red, &blue, (red_score, blue_score));
+        }
+
+        records
+    }
+
+    fn eliminations(&mut self, teams: &[Team], records: &[Record]) {
+        let mut ranked: Vec<usize> = (0..teams.len()).collect();
+        ranked.sort_by_key(|&team| {
+            let record = records[team];
+            (std::cmp::Reverse(record.wins * 2 + record.ties), std::cmp::Reverse(record.points))
+        });
+
+        let alliance_count = [16, 8, 4, 2].into_iter()
+            .find(|&count| teams.len() >= count * 2)
+            .unwrap_or(0);
+        if alliance_count == 0 {
+            return;
+        }
+
+        // each captain picks the best ranked team still available, in rank order
+        let alliances: Vec<[usize; 2]> = ranked.chunks(2)
+            .take(alliance_count)
+            .map(|pair| [pair[0], pair[1]])
+            .collect();
+
+        self.next_slot += Duration::seconds(ALLIANCE_SELECTION_SECS);
+
+        // standard seeding, so the top two alliances can only meet in the final
+        let mut bracket: Vec<usize> = seeding(alliance_count);
+        while bracket.len() > 1 {
+            let (round, name) = match bracket.len() {
+                16 => (6, "Round of 16"),
+                8 => (3, "Quarterfinal"),
+                4 => (4, "Semifinal"),
+                _ => (5, "Final"),
+            };
+
+            bracket = bracket.chunks(2)
+                .enumerate()
+                .map(|(instance, pair)| {
+                    let instance = instance as i32 + 1;
+                    let (high, low) = (pair[0].min(pair[1]), pair[0].max(pair[1]));
+                    let (red, blue) = (alliances[high], alliances[low]);
+
+                    // ties are replayed until someone wins
+                    let mut matchnum = 1;
+                    loop {
+                        let scores = self.play(teams, &red, &blue);
+                        self.push_match(round, instance, matchnum, format!("{} #{}-{}", name, instance, matchnum), teams, &red, &blue, scores);
+
+                        if scores.0 != scores.1 {
+                            return if scores.0 > scores.1 { high } else { low };
+                        }
+                        matchnum += 1;
+                    }
+                })
+                .collect();
+        }
+    }
+
+    fn play(&mut self, teams: &[Team], red: &[usize; 2], blue: &[usize; 2]) -> (i32, i32) {
+        let mut score = |alliance: &[usize; 2]| {
+            let strength: i32 = alliance.iter().map(|&team| teams[team].strength).sum();
+            (strength + self.rng.gen_range(-25..=25)).max(0)
+        };
+
+        (score(red), score(blue))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn push_match(
+        &mut self,
+        round: i32,
+        instance: i32,
+        matchnum: i32,
+        name: String,
+        teams: &[Team],
+        red: &[usize; 2],
+        blue: &[usize; 2],
+        scores: (i32, i32),
+    ) {
+        let scheduled = self.next_slot;
+        self.next_slot += Duration::seconds(MATCH_GAP_SECS);
+
+        // events drift a little behind every match, catch up occasionally, and now and
+        // then lose several minutes to a field fault
+        self.delay_secs = (self.delay_secs + self.rng.gen_range(-45..=60)).max(0);
+        if self.rng.gen_bool(0.03) {
+            self.delay_secs += self.rng.gen_range(180..=600);
+        }
+        let started = scheduled + Duration::seconds(self.delay_secs);
+
+        let alliance = |color: AllianceColor, members: &[usize; 2], score: i32| Alliance {
+            color,
+            score,
+            teams: members.iter()
+                .map(|&team| AllianceTeam {
+                    team: teams[team].info.clone(),
+                    sitting: false,
+                })
+                .collect(),
+        };
+
+        let competition_id = self.spec.competition_id;
+        self.matches.push(Match {
+            id: self.division.id * 100_000 + self.matches.len() as i32 + 1,
+            event: IdInfo {
+                id: competition_id,
+                name: "Synthetic Event".to_string(),
+                code: None,
+            },
+            division: self.division.clone(),
+            round,
+            instance,
+            matchnum,
+            scheduled: Some(scheduled.to_rfc3339()),
+            started: Some(started.to_rfc3339()),
+            field: Some(format!("Field {}", self.matches.len() % 2 + 1)),
+            scored: true,
+            name,
+            alliances: vec![
+                alliance(AllianceColor::Blue, blue, scores.1),
+                alliance(AllianceColor::Red, red, scores.0),
+            ],
+        });
+    }
+}
+
+/// Alliance indices in bracket order for `count` alliances (a power of two), e.g.
+/// `[0, 7, 3, 4, 1, 6, 2, 5]` for eight.
+fn seeding(count: usize) -> Vec<usize> {
+    let mut order = vec![0];
+    while order.len() < count {
+        let size = order.len() * 2;
+        order = order.into_iter()
+            .flat_map(|seed| [seed, size - 1 - seed])
+            .collect();
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn matches_by_team(matches: &[Match]) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for m in matches.iter().filter(|m| m.round == 2) {
+            for alliance_team in m.alliances.iter().flat_map(|alliance| alliance.teams.iter()) {
+                *counts.entry(alliance_team.team.name.clone()).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    fn spec(teams: usize) -> EventSpec {
+        EventSpec {
+            competition_id: 1,
+            divisions: 2,
+            teams,
+            matches_per_team: 6,
+            seed: 7,
+        }
+    }
+
+    #[test]
+    fn qualifications_give_every_team_an_even_schedule() {
+        let matches = generate(&spec(40));
+        assert_eq!(matches, generate(&spec(40)));
+
+        for m in matches.iter().filter(|m| m.round == 2) {
+            let names: HashSet<&str> = m.alliances.iter()
+                .flat_map(|alliance| alliance.teams.iter())
+                .map(|alliance_team| alliance_team.team.name.as_str())
+                .collect();
+            assert_eq!(names.len(), 4);
+        }
+
+        let counts = matches_by_team(&matches);
+        assert_eq!(counts.len(), 80);
+        assert!(counts.values().all(|&count| (6..=7).contains(&count)));
+
+        let ids: HashSet<i32> = matches.iter().map(|m| m.id).collect();
+        assert_eq!(ids.len(), matches.len());
+    }
+
+    #[test]
+    fn bracket_runs_from_round_of_16_to_a_single_final() {
+        let matches = generate(&EventSpec { divisions: 1, ..spec(64) });
+        let series = |round| matches.iter()
+            .filter(|m| m.round == round)
+            .map(|m| m.instance)
+            .collect::<HashSet<_>>()
+            .len();
+
+        assert_eq!(series(6), 8);
+        assert_eq!(series(3), 4);
+        assert_eq!(series(4), 2);
+        assert_eq!(series(5), 1);
+
+        // every elimination series ends with a decisive match
+        let last_final = matches.iter().rfind(|m| m.round == 5).unwrap();
+        assert_ne!(last_final.alliances[0].score, last_final.alliances[1].score);
+
+        assert_eq!(seeding(8), vec![0, 7, 3, 4, 1, 6, 2, 5]);
+    }
+}
@@ -0,0 +1,919 @@
+use hmac::{Hmac, Mac};
+use hyper::client::connect::dns::Name;
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::{Body, Client, Method, Request, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, RwLock};
+use warp::http;
+use crate::apiError::ApiError;
+use crate::cluster::{self, Cluster};
+use crate::divisionStream::DivisionUpdate;
+use crate::matchDiff::MatchChange;
+use crate::{CompetitionDivisionPair, StateStore};
+
+/// Attempts per event before the delivery counts as failed.
+const MAX_ATTEMPTS: u32 = 5;
+/// Failed deliveries in a row before a webhook is switched off.
+const DISABLE_AFTER_FAILURES: u32 = 10;
+/// How many delivery log entries to keep per webhook.
+const DELIVERY_LOG_SIZE: usize = 50;
+/// Events waiting to go out to one webhook; past this, new ones are dropped.
+const QUEUE_SIZE: usize = 256;
+/// Times an event waits out a 429's `Retry-After` before it's given up on.
+const MAX_RATE_LIMITED_WAITS: u32 = 5;
+/// The longest `Retry-After` honoured.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// An event name and the JSON body to post.
+type QueuedEvent = (String, String);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookRequest {
+    url: String,
+    competition_id: i32,
+    division_id: i32,
+    /// only send events for matches involving one of these teams
+    #[serde(default)]
+    teams: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Webhook {
+    id: String,
+    url: String,
+    division: CompetitionDivisionPair,
+    teams: Option<Vec<String>>,
+    #[serde(skip)]
+    secret: String,
+    /// the install that registered it, which alone may see or change it
+    #[serde(skip)]
+    install_id: Option<String>,
+    enabled: bool,
+    consecutive_failures: u32,
+    #[serde(skip)]
+    deliveries: VecDeque<DeliveryLog>,
+    /// events waiting for delivery, sent one at a time; started by the first dispatch
+    #[serde(skip)]
+    queue: Option<mpsc::Sender<QueuedEvent>>,
+}
+
+impl Webhook {
+    fn owned_by(&self, install_id: Option<&str>) -> bool {
+        self.install_id.as_deref() == install_id
+    }
+
+    fn wants(&self, division: &CompetitionDivisionPair, change: &MatchChange) -> bool {
+        if !self.enabled || &self.division != division {
+            return false;
+        }
+
+        match &self.teams {
+            None => true,
+            Some(teams) => teams.iter().any(|team| change.display_match().involves_team(team)),
+        }
+    }
+
+    fn stored(&self) -> StoredWebhook {
+        StoredWebhook {
+            id: self.id.clone(),
+            url: self.url.clone(),
+            division: self.division.clone(),
+            teams: self.teams.clone(),
+            secret: self.secret.clone(),
+            install_id: self.install_id.clone(),
+            enabled: self.enabled,
+        }
+    }
+
+    /// Take on changes another instance made: switched off (dropping anything queued) or back on.
+    fn update(&mut self, stored: StoredWebhook) {
+        if stored.enabled && !self.enabled {
+            self.consecutive_failures = 0;
+        }
+        if !stored.enabled {
+            self.queue = None;
+        }
+        self.url = stored.url;
+        self.teams = stored.teams;
+        self.enabled = stored.enabled;
+    }
+}
+
+/// A webhook as kept between restarts and shared between instances; its delivery log
+/// starts afresh.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredWebhook {
+    pub id: String,
+    url: String,
+    division: CompetitionDivisionPair,
+    teams: Option<Vec<String>>,
+    secret: String,
+    #[serde(default)]
+    install_id: Option<String>,
+    enabled: bool,
+}
+
+impl From<StoredWebhook> for Webhook {
+    fn from(stored: StoredWebhook) -> Self {
+        Webhook {
+            id: stored.id,
+            url: stored.url,
+            division: stored.division,
+            teams: stored.teams,
+            secret: stored.secret,
+            install_id: stored.install_id,
+            enabled: stored.enabled,
+            consecutive_failures: 0,
+            deliveries: VecDeque::new(),
+            queue: None,
+        }
+    }
+}
+
+/// Returned once at registration; the secret is never shown again.
+#[derive(Serialize, Debug)]
+pub struct WebhookCreated {
+    #[serde(flatten)]
+    webhook: Webhook,
+    secret: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeliveryLog {
+    event: String,
+    attempt: u32,
+    status: Option<u16>,
+    error: Option<String>,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookRegistry {
+    hooks: Arc<RwLock<HashMap<String, Webhook>>>,
+    client: Client<HttpsConnector<HttpConnector<PublicResolver>>>,
+    retry_base: Duration,
+    /// only deliver over https to public addresses; tests turn this off for a local listener
+    public_https_only: bool,
+    /// where webhooks are registered so every instance knows them; `hooks` mirrors it
+    cluster: Cluster,
+}
+
+impl WebhookRegistry {
+    pub fn new() -> Self {
+        let mut http = HttpConnector::new_with_resolver(PublicResolver);
+        http.enforce_http(false);
+        let https = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .wrap_connector(http);
+
+        WebhookRegistry {
+            hooks: Arc::new(RwLock::new(HashMap::new())),
+            client: Client::builder().build::<_, Body>(https),
+            retry_base: Duration::from_secs(2),
+            public_https_only: true,
+            cluster: Cluster::default(),
+        }
+    }
+
+    pub fn with_cluster(self, cluster: Cluster) -> Self {
+        WebhookRegistry { cluster, ..self }
+    }
+
+    /// A registry that delivers to local http listeners and retries almost at once, for tests.
+    #[cfg(test)]
+    pub fn local() -> Self {
+        WebhookRegistry {
+            retry_base: Duration::from_millis(1),
+            public_https_only: false,
+            ..WebhookRegistry::new()
+        }
+    }
+
+    /// Register a webhook for `install_id`, which may have at most `max_per_install`. `admit` is
+    /// asked, under the same lock the webhook is added under, whether its division fits
+    /// alongside those enabled webhooks already want.
+    pub async fn register(
+        &self,
+        request: WebhookRequest,
+        install_id: Option<String>,
+        max_per_install: usize,
+        admit: impl FnOnce(&CompetitionDivisionPair, &[CompetitionDivisionPair]) -> Result<(), ApiError>,
+    ) -> Result<WebhookCreated, ApiError> {
+        let invalid = |message: String| ApiError::bad_request("invalid_webhook", message);
+        let uri: Uri = request.url.parse().map_err(|_| invalid("url is not a valid URL".to_string()))?;
+        if self.public_https_only {
+            check_target(&uri).await.map_err(invalid)?;
+        } else if !matches!(uri.scheme_str(), Some("http") | Some("https")) {
+            return Err(invalid("url must be http or https".to_string()));
+        }
+
+        let webhook = Webhook {
+            id: random_hex(16),
+            url: request.url,
+            division: CompetitionDivisionPair::new(request.competition_id, request.division_id),
+            teams: request.teams,
+            secret: random_hex(32),
+            install_id,
+            enabled: true,
+            consecutive_failures: 0,
+            deliveries: VecDeque::new(),
+            queue: None,
+        };
+
+        self.refresh().await;
+        let mut hooks = self.hooks.write().await;
+        if hooks.values().filter(|existing| existing.owned_by(webhook.install_id.as_deref())).count() >= max_per_install {
+            return Err(ApiError::new(
+                http::StatusCode::TOO_MANY_REQUESTS,
+                "too_many_webhooks",
+                format!("an install may register at most {} webhooks", max_per_install),
+            ));
+        }
+        admit(&webhook.division, &enabled_divisions(&hooks))?;
+
+        tracing::info!(id = webhook.id, division = ?webhook.division, url = webhook.url, "registering webhook");
+        self.cluster.share_webhook(webhook.stored()).await.map_err(cluster::unavailable)?;
+        hooks.insert(webhook.id.clone(), webhook.clone());
+
+        Ok(WebhookCreated {
+            secret: webhook.secret.clone(),
+            webhook,
+        })
+    }
+
+    /// Bring the local webhooks in line with the cluster store, where every instance registers
+    /// them. Without a store, or if it can't be read, they stay as they are.
+    pub async fn refresh(&self) {
+        let shared = match self.cluster.shared_webhooks().await {
+            None => return,
+            Some(Ok(shared)) => shared,
+            Some(Err(e)) => {
+                tracing::error!(error = %e, "unable to read shared webhooks");
+                return;
+            }
+        };
+
+        let mut hooks = self.hooks.write().await;
+        hooks.retain(|id, _| shared.iter().any(|stored| &stored.id == id));
+        for stored in shared {
+            match hooks.get_mut(&stored.id) {
+                Some(webhook) => webhook.update(stored),
+                None => {
+                    hooks.insert(stored.id.clone(), Webhook::from(stored));
+                }
+            }
+        }
+    }
+
+    /// The webhook `id`, if `install_id` registered it. Anyone else is told there's no such webhook.
+    pub async fn get(&self, id: &str, install_id: Option<&str>) -> Option<Webhook> {
+        self.refresh().await;
+        self.hooks.read().await.get(id).filter(|webhook| webhook.owned_by(install_id)).cloned()
+    }
+
+    pub async fn remove(&self, id: &str, install_id: Option<&str>) -> Result<Option<Webhook>, ApiError> {
+        self.refresh().await;
+        let mut hooks = self.hooks.write().await;
+        if hooks.get(id).filter(|webhook| webhook.owned_by(install_id)).is_none() {
+            return Ok(None);
+        }
+
+        self.cluster.remove_shared_webhook(id).await.map_err(cluster::unavailable)?;
+        Ok(hooks.remove(id))
+    }
+
+    /// Turn a webhook back on after it was disabled for failing.
+    pub async fn enable(&self, id: &str, install_id: Option<&str>) -> Result<Option<Webhook>, ApiError> {
+        self.refresh().await;
+        let mut hooks = self.hooks.write().await;
+        let Some(webhook) = hooks.get_mut(id).filter(|webhook| webhook.owned_by(install_id)) else {
+            return Ok(None);
+        };
+
+        self.cluster.share_webhook(StoredWebhook { enabled: true, ..webhook.stored() }).await.map_err(cluster::unavailable)?;
+        webhook.enabled = true;
+        webhook.consecutive_failures = 0;
+        Ok(Some(webhook.clone()))
+    }
+
+    /// The divisions enabled webhooks want, which need polling for them.
+    pub async fn divisions(&self) -> Vec<CompetitionDivisionPair> {
+        enabled_divisions(&*self.hooks.read().await)
+    }
+
+    pub async fn snapshot(&self) -> Vec<StoredWebhook> {
+        self.hooks.read().await.values().map(Webhook::stored).collect()
+    }
+
+    /// Put back webhooks saved by `snapshot`, registering them with the cluster store again in
+    /// case it has lost them.
+    pub async fn restore(&self, stored: Vec<StoredWebhook>) {
+        let mut hooks = self.hooks.write().await;
+
+        for stored in stored {
+            if let Err(e) = self.cluster.share_webhook(stored.clone()).await {
+                tracing::error!(id = stored.id, error = %e, "unable to share restored webhook");
+            }
+            hooks.insert(stored.id.clone(), Webhook::from(stored));
+        }
+    }
+
+    /// Installs that have registered a webhook.
+    pub async fn install_ids(&self) -> HashSet<String> {
+        self.hooks.read().await.values().filter_map(|webhook| webhook.install_id.clone()).collect()
+    }
+
+    /// The webhook's recent delivery attempts, as made by this instance. In a cluster only the
+    /// instance that owns its division delivers, so the log is elsewhere empty.
+    pub async fn deliveries(&self, id: &str, install_id: Option<&str>) -> Option<Vec<DeliveryLog>> {
+        self.refresh().await;
+        let hooks = self.hooks.read().await;
+        let webhook = hooks.get(id).filter(|webhook| webhook.owned_by(install_id))?;
+        Some(webhook.deliveries.iter().cloned().collect())
+    }
+
+    /// Queue a delivery of every change in `update` to the webhooks that want it. Each webhook
+    /// gets its events one at a time, in order.
+    pub async fn dispatch(&self, update: &DivisionUpdate) {
+        let mut hooks = self.hooks.write().await;
+
+        for webhook in hooks.values_mut() {
+            let events: Vec<QueuedEvent> = update.changes.iter()
+                .filter(|change| webhook.wants(&update.division, change))
+                .map(|change| {
+                    // corrections and substitutions also say what the match looked like before
+                    let previous = match change {
+                        MatchChange::ScoreCorrected { previous, .. } | MatchChange::TeamsChanged { previous, .. } => Some(previous),
+                        _ => None,
+                    };
+                    let body = json!({
+                        "event": change.name(),
+                        "competition_id": update.division.competition_id,
+                        "division_id": update.division.division_id,
+                        "match": change.display_match(),
+                        "previous": previous,
+                        "timestamp": chrono::Utc::now().timestamp(),
+                    })
+                    .to_string();
+                    (change.name().to_string(), body)
+                })
+                .collect();
+            if events.is_empty() {
+                continue;
+            }
+
+            let queue = webhook.queue.get_or_insert_with(|| {
+                let (queue, events) = mpsc::channel(QUEUE_SIZE);
+                tokio::spawn(self.clone().drain(webhook.id.clone(), events));
+                queue
+            });
+            for event in events {
+                if let Err(e) = queue.try_send(event) {
+                    let (event, _) = e.into_inner();
+                    tracing::warn!(id = webhook.id, event, "webhook queue is full; dropping event");
+                }
+            }
+        }
+    }
+
+    /// Deliver a webhook's queued events one after another until it's removed or disabled.
+    async fn drain(self, id: String, mut events: mpsc::Receiver<QueuedEvent>) {
+        while let Some((event, body)) = events.recv().await {
+            self.deliver(&id, event, body).await;
+        }
+    }
+
+    async fn deliver(&self, id: &str, event: String, body: String) {
+        let mut attempt = 1;
+        let mut rate_limited = 0;
+
+        loop {
+            let Some((url, secret)) = self.hooks.read().await.get(id)
+                .filter(|webhook| webhook.enabled)
+                .map(|webhook| (webhook.url.clone(), webhook.secret.clone()))
+            else {
+                // removed or disabled since the event was queued
+                return;
+            };
+
+            let result = self.post(&url, &secret, &body).await;
+            let succeeded = matches!(result, Ok((status, _)) if status.is_success());
+            let retry_after = match &result {
+                Ok((http::StatusCode::TOO_MANY_REQUESTS, retry_after)) => *retry_after,
+                _ => None,
+            };
+
+            self.record(id, DeliveryLog {
+                event: event.clone(),
+                attempt,
+                status: result.as_ref().ok().map(|(status, _)| status.as_u16()),
+                error: result.err().map(|e| e.to_string()),
+                timestamp: chrono::Utc::now(),
+            })
+            .await;
+
+            if succeeded {
+                if let Some(webhook) = self.hooks.write().await.get_mut(id) {
+                    webhook.consecutive_failures = 0;
+                }
+                return;
+            }
+
+            // the receiver is up and asking us to slow down, which isn't a failed attempt
+            if let Some(retry_after) = retry_after {
+                rate_limited += 1;
+                if rate_limited > MAX_RATE_LIMITED_WAITS {
+                    tracing::warn!(id, event, "webhook kept rate limiting; dropping event");
+                    return;
+                }
+                tokio::time::sleep(retry_after.min(MAX_RETRY_AFTER)).await;
+                continue;
+            }
+
+            if attempt == MAX_ATTEMPTS {
+                break;
+            }
+            tokio::time::sleep(self.retry_base * 2u32.pow(attempt - 1)).await;
+            attempt += 1;
+        }
+
+        let disabled = {
+            let mut hooks = self.hooks.write().await;
+            let Some(webhook) = hooks.get_mut(id) else { return };
+            webhook.consecutive_failures += 1;
+            if !webhook.enabled || webhook.consecutive_failures < DISABLE_AFTER_FAILURES {
+                return;
+            }
+
+            tracing::warn!(id, failures = webhook.consecutive_failures, "disabling webhook after repeated failed deliveries");
+            webhook.enabled = false;
+            // whatever is still queued is skipped, and the queue starts afresh if it's enabled again
+            webhook.queue = None;
+            webhook.stored()
+        };
+
+        // its division stops being polled for it, here and on every other instance
+        if let Err(e) = self.cluster.share_webhook(disabled).await {
+            tracing::error!(id, error = %e, "unable to share disabled webhook");
+        }
+    }
+
+    /// POST `body` to `url`, returning the status and, if the receiver sent one, how long it
+    /// asked us to wait before trying again.
+    async fn post(&self, url: &str, secret: &str, body: &str) -> Result<(http::StatusCode, Option<Duration>), Box<dyn Error + Send + Sync>> {
+        // the host may have been pointed somewhere internal since it was registered
+        if self.public_https_only {
+            check_target(&url.parse()?).await?;
+        }
+
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(url)
+            .header("content-type", "application/json")
+            .header("x-echoscope-timestamp", &timestamp)
+            .header("x-echoscope-signature", format!("sha256={}", sign(secret, &timestamp, body)))
+            .body(Body::from(body.to_string()))?;
+
+        let res = tokio::time::timeout(Duration::from_secs(10), self.client.request(req)).await??;
+        let retry_after = res.headers().get(http::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs);
+
+        Ok((res.status(), retry_after))
+    }
+
+    async fn record(&self, id: &str, log: DeliveryLog) {
+        if let Some(webhook) = self.hooks.write().await.get_mut(id) {
+            if webhook.deliveries.len() == DELIVERY_LOG_SIZE {
+                webhook.deliveries.pop_front();
+            }
+            webhook.deliveries.push_back(log);
+        }
+    }
+}
+
+/// Whether `ip` is out on the internet, rather than loopback, a private or carrier-grade NAT
+/// range, link-local (which covers the 169.254.169.254 metadata address), Fly's private
+/// `fdaa::` network, or some other special-purpose block. IPv6 addresses that embed an IPv4
+/// one (mapped, compatible or NAT64) are judged by the IPv4 address.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || first == 0
+                || first >= 240
+                || (first == 100 && second & 0xc0 == 64)
+                || (first == 198 && second & 0xfe == 18))
+        }
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// The IPv4 address inside an IPv4-mapped (`::ffff:a.b.c.d`), IPv4-compatible (`::a.b.c.d`)
+/// or NAT64 (`64:ff9b::a.b.c.d`) address.
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    match ip.segments() {
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => Some(Ipv4Addr::from(((high as u32) << 16) | low as u32)),
+        _ => ip.to_ipv4(),
+    }
+}
+
+/// Turn away a webhook URL unless it's https to a host whose every address is public.
+async fn check_target(uri: &Uri) -> Result<(), String> {
+    if uri.scheme_str() != Some("https") {
+        return Err("url must be https".to_string());
+    }
+    let host = uri.host().ok_or_else(|| "url must have a host".to_string())?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, uri.port_u16().unwrap_or(443))).await
+        .map_err(|_| format!("unable to resolve {}", host))?
+        .collect();
+    if addresses.is_empty() || !addresses.iter().all(|address| is_public(address.ip())) {
+        return Err("url must point at a public address".to_string());
+    }
+
+    Ok(())
+}
+
+/// Resolves hostnames to their public addresses only, so a name switched to an internal
+/// address between `check_target` and connecting still can't reach it.
+#[derive(Debug, Clone)]
+struct PublicResolver;
+
+impl Service<Name> for PublicResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0)).await?
+                .filter(|address| is_public(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} has no public address", name)));
+            }
+            Ok(addresses.into_iter())
+        })
+    }
+}
+
+/// HMAC-SHA256 over `"{timestamp}.{body}"`, hex encoded. Receivers should recompute this
+/// with their secret and reject stale timestamps.
+pub fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn random_hex(bytes: usize) -> String {
+    let mut rng = rand::thread_rng();
+    hex::encode((0..bytes).map(|_| rng.gen::<u8>()).collect::<Vec<u8>>())
+}
+
+fn enabled_divisions(hooks: &HashMap<String, Webhook>) -> Vec<CompetitionDivisionPair> {
+    let mut divisions: Vec<CompetitionDivisionPair> = Vec::new();
+    for webhook in hooks.values().filter(|webhook| webhook.enabled) {
+        if !divisions.contains(&webhook.division) {
+            divisions.push(webhook.division.clone());
+        }
+    }
+    divisions
+}
+
+/// Forward every change in the divisions this instance owns to the registered webhooks until
+/// shutdown. Other instances deliver for theirs.
+pub async fn run(state_store: StateStore) {
+    let mut updates = state_store.updates.subscribe();
+
+    loop {
+        let update = tokio::select! {
+            update = updates.recv() => update,
+            _ = state_store.shutdown.requested() => return,
+        };
+
+        match update {
+            Ok(update) if state_store.cluster.owns(&update.division) => state_store.webhooks.dispatch(&update).await,
+            Ok(_) => {}
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::error!(skipped, "webhooks fell behind and skipped division updates");
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+pub async fn add_webhook(
+    install_id: Option<String>,
+    request: WebhookRequest,
+    state_store: StateStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let division = CompetitionDivisionPair::new(request.competition_id, request.division_id);
+    state_store.can_poll(&division).await?;
+    state_store.validate_division(&division).await?;
+
+    // held until the webhook is added, so a subscription can't take the last division meanwhile
+    let subscriptions = state_store.subscriptions.read().await;
+    let admit = |division: &CompetitionDivisionPair, webhooks: &[CompetitionDivisionPair]| {
+        let watched = state_store.watched.lock().unwrap();
+        StateStore::admit_division(division, state_store.config.limits.max_divisions, subscriptions.keys(), webhooks, &watched)
+            .map_err(ApiError::from)
+    };
+    let created = state_store.webhooks
+        .register(request, install_id, state_store.config.limits.max_webhooks_per_install, admit)
+        .await?;
+
+    Ok(warp::reply::with_status(warp::reply::json(&created), http::StatusCode::CREATED))
+}
+
+fn unknown_webhook() -> ApiError {
+    ApiError::not_found("unknown_webhook", "no webhook with that id")
+}
+
+pub async fn get_webhook(id: String, install_id: Option<String>, state_store: StateStore) -> Result<impl warp::Reply, warp::Rejection> {
+    let webhook = state_store.webhooks.get(&id, install_id.as_deref()).await.ok_or_else(unknown_webhook)?;
+    Ok(warp::reply::json(&webhook))
+}
+
+pub async fn enable_webhook(id: String, install_id: Option<String>, state_store: StateStore) -> Result<impl warp::Reply, warp::Rejection> {
+    let webhook = state_store.webhooks.enable(&id, install_id.as_deref()).await?.ok_or_else(unknown_webhook)?;
+    Ok(warp::reply::json(&webhook))
+}
+
+pub async fn get_deliveries(id: String, install_id: Option<String>, state_store: StateStore) -> Result<impl warp::Reply, warp::Rejection> {
+    let deliveries = state_store.webhooks.deliveries(&id, install_id.as_deref()).await.ok_or_else(unknown_webhook)?;
+    Ok(warp::reply::json(&deliveries))
+}
+
+pub async fn remove_webhook(id: String, install_id: Option<String>, state_store: StateStore) -> Result<impl warp::Reply, warp::Rejection> {
+    state_store.webhooks.remove(&id, install_id.as_deref()).await?.ok_or_else(unknown_webhook)?;
+    Ok(warp::reply::with_status("Removed webhook", http::StatusCode::OK))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::competitionAttributes::{Alliance, DisplayMatch};
+    use std::sync::Mutex;
+    use warp::Filter;
+
+    /// (timestamp, signature, body) for each request the listener received
+    type Received = Arc<Mutex<Vec<(String, String, String)>>>;
+
+    /// A local listener that answers every POST with `status` and records what it got.
+    fn listener(status: http::StatusCode) -> (String, Received) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let store = received.clone();
+
+        let route = warp::post()
+            .and(warp::header::<String>("x-echoscope-timestamp"))
+            .and(warp::header::<String>("x-echoscope-signature"))
+            .and(warp::body::bytes())
+            .map(move |timestamp: String, signature: String, body: hyper::body::Bytes| {
+                store.lock().unwrap().push((timestamp, signature, String::from_utf8_lossy(&body).to_string()));
+                warp::reply::with_status("", status)
+            });
+
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        (format!("http://{}/hook", addr), received)
+    }
+
+    fn test_registry() -> WebhookRegistry {
+        WebhookRegistry::local()
+    }
+
+    fn score_posted(red: &str, blue: &str) -> DivisionUpdate {
+        let alliance = |team: &str, score| Alliance { team1: team.to_string(), team2: None, score: Some(score) };
+
+        DivisionUpdate {
+            division: CompetitionDivisionPair::new(1, 1),
+            matches: Vec::new(),
+            changes: vec![MatchChange::ScorePosted {
+                display_match: DisplayMatch {
+                    name: "Q1".to_string(),
+                    scheduled: None,
+                    start_time: None,
+                    red_alliance: alliance(red, 10),
+                    blue_alliance: alliance(blue, 5),
+                    corrected: false,
+                },
+            }],
+        }
+    }
+
+    const INSTALL: Option<&str> = Some("0123456789abcdef");
+
+    fn request(url: &str, teams: Option<Vec<String>>) -> WebhookRequest {
+        WebhookRequest {
+            url: url.to_string(),
+            competition_id: 1,
+            division_id: 1,
+            teams,
+        }
+    }
+
+    async fn register(registry: &WebhookRegistry, url: &str, teams: Option<Vec<String>>) -> WebhookCreated {
+        registry.register(request(url, teams), INSTALL.map(String::from), 5, |_, _| Ok(())).await.unwrap()
+    }
+
+    async fn wait_for<F: Fn() -> bool>(condition: F) {
+        for _ in 0..200 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not met in time");
+    }
+
+    #[tokio::test]
+    async fn delivers_signed_events() {
+        let (url, received) = listener(http::StatusCode::OK);
+        let registry = test_registry();
+        let created = register(&registry, &url, None).await;
+
+        registry.dispatch(&score_posted("1A", "2A")).await;
+        wait_for(|| received.lock().unwrap().len() == 1).await;
+
+        let (timestamp, signature, body) = received.lock().unwrap()[0].clone();
+        assert_eq!(signature, format!("sha256={}", sign(&created.secret, &timestamp, &body)));
+
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["event"], "score_posted");
+        assert_eq!(body["match"]["name"], "Q1");
+    }
+
+    #[tokio::test]
+    async fn filters_by_team() {
+        let (url, received) = listener(http::StatusCode::OK);
+        let registry = test_registry();
+        register(&registry, &url, Some(vec!["3a".to_string()])).await;
+
+        registry.dispatch(&score_posted("1A", "2A")).await;
+        registry.dispatch(&score_posted("3A", "2A")).await;
+        wait_for(|| received.lock().unwrap().len() == 1).await;
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(received.lock().unwrap().len(), 1);
+        assert!(received.lock().unwrap()[0].2.contains("3A"));
+    }
+
+    #[tokio::test]
+    async fn retries_then_disables_failing_webhooks() {
+        let (url, received) = listener(http::StatusCode::INTERNAL_SERVER_ERROR);
+        let registry = test_registry();
+        let id = register(&registry, &url, None).await.webhook.id;
+
+        for _ in 0..DISABLE_AFTER_FAILURES {
+            registry.dispatch(&score_posted("1A", "2A")).await;
+        }
+
+        let expected = (MAX_ATTEMPTS * DISABLE_AFTER_FAILURES) as usize;
+        wait_for(|| received.lock().unwrap().len() == expected).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let webhook = registry.get(&id, INSTALL).await.unwrap();
+        assert!(!webhook.enabled);
+        assert!(registry.divisions().await.is_empty());
+        assert_eq!(registry.deliveries(&id, INSTALL).await.unwrap().len(), DELIVERY_LOG_SIZE);
+        assert_eq!(registry.deliveries(&id, INSTALL).await.unwrap()[0].status, Some(500));
+    }
+
+    #[tokio::test]
+    async fn delivers_in_order_and_waits_out_rate_limits() {
+        // every other request is turned away with a 429, as a busy receiver would
+        let received: Received = Arc::new(Mutex::new(Vec::new()));
+        let store = received.clone();
+        let requests = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let route = warp::post()
+            .and(warp::body::bytes())
+            .map(move |body: hyper::body::Bytes| {
+                if requests.fetch_add(1, std::sync::atomic::Ordering::SeqCst).is_multiple_of(2) {
+                    return warp::http::Response::builder().status(429).header("retry-after", "0").body(String::new()).unwrap();
+                }
+                store.lock().unwrap().push((String::new(), String::new(), String::from_utf8_lossy(&body).to_string()));
+                warp::http::Response::new(String::new())
+            });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let registry = test_registry();
+        let id = register(&registry, &format!("http://{}/hook", addr), None).await.webhook.id;
+
+        let teams = ["1A", "2A", "3A", "4A", "5A", "6A", "7A", "8A"];
+        for team in teams {
+            registry.dispatch(&score_posted(team, "9A")).await;
+        }
+        wait_for(|| received.lock().unwrap().len() == teams.len()).await;
+
+        let delivered: Vec<String> = received.lock().unwrap().iter()
+            .map(|(_, _, body)| serde_json::from_str::<serde_json::Value>(body).unwrap()["match"]["redAlliance"]["team1"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(delivered, teams);
+
+        let webhook = registry.get(&id, INSTALL).await.unwrap();
+        assert!(webhook.enabled);
+        assert_eq!(webhook.consecutive_failures, 0);
+        assert!(registry.deliveries(&id, INSTALL).await.unwrap().iter().all(|log| log.attempt == 1));
+    }
+
+    #[tokio::test]
+    async fn only_the_registering_install_sees_or_changes_a_webhook() {
+        let registry = test_registry();
+        let id = register(&registry, "http://127.0.0.1:9/hook", None).await.webhook.id;
+        let other = Some("fedcba9876543210");
+
+        assert!(registry.get(&id, other).await.is_none());
+        assert!(registry.get(&id, None).await.is_none());
+        assert!(registry.deliveries(&id, other).await.is_none());
+        assert!(registry.enable(&id, other).await.unwrap().is_none());
+        assert!(registry.remove(&id, other).await.unwrap().is_none());
+        assert!(registry.remove(&id, INSTALL).await.unwrap().is_some());
+
+        // each install gets its own allowance
+        for _ in 0..2 {
+            registry.register(request("http://127.0.0.1:9/hook", None), INSTALL.map(String::from), 2, |_, _| Ok(())).await.unwrap();
+        }
+        let refused = registry.register(request("http://127.0.0.1:9/hook", None), INSTALL.map(String::from), 2, |_, _| Ok(())).await.unwrap_err();
+        assert_eq!(refused.code, "too_many_webhooks");
+        assert!(registry.register(request("http://127.0.0.1:9/hook", None), other.map(String::from), 2, |_, _| Ok(())).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn only_public_https_targets_are_accepted() {
+        let registry = WebhookRegistry::new();
+        for url in [
+            "http://hooks.example.com/hook",
+            "https://127.0.0.1/hook",
+            "https://localhost:8080/hook",
+            "https://10.1.2.3/hook",
+            "https://169.254.169.254/latest/meta-data",
+            "https://[::1]/hook",
+            "https://[fdaa::3]/hook",
+            "https://[::ffff:192.168.0.1]/hook",
+        ] {
+            assert!(registry.register(request(url, None), INSTALL.map(String::from), 5, |_, _| Ok(())).await.is_err(), "{} was accepted", url);
+        }
+
+        assert!(is_public("93.184.215.14".parse().unwrap()));
+        assert!(is_public("2606:4700::1111".parse().unwrap()));
+        assert!(!is_public("100.64.0.1".parse().unwrap()));
+        assert!(!is_public("fe80::1".parse().unwrap()));
+        assert!(!is_public("198.18.0.1".parse().unwrap()));
+        assert!(!is_public("198.19.255.254".parse().unwrap()));
+        assert!(is_public("198.20.0.1".parse().unwrap()));
+        assert!(!is_public("240.0.0.1".parse().unwrap()));
+        assert!(!is_public("64:ff9b::10.0.0.1".parse().unwrap()));
+        assert!(!is_public("64:ff9b::a9fe:a9fe".parse().unwrap()));
+        assert!(is_public("64:ff9b::93.184.215.14".parse().unwrap()));
+        assert!(!is_public("::127.0.0.1".parse().unwrap()));
+        assert!(!is_public("::192.168.0.1".parse().unwrap()));
+        assert!(!is_public("::".parse().unwrap()));
+        assert!(!is_public("::1".parse().unwrap()));
+    }
+}